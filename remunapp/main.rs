@@ -1,9 +1,9 @@
-#![feature(iter_array_chunks, try_blocks)]
+#![feature(try_blocks)]
 use ::egui::FontDefinitions;
 use egui_wgpu_backend::{RenderPass, ScreenDescriptor};
 use egui_winit_platform::{Platform, PlatformDescriptor};
 use remun::State;
-use shared::Ines;
+use remun::controller::Port;
 use std::time::Instant;
 use std::{env, error::Error, sync::Arc};
 use visualizer::Visualizer;
@@ -13,6 +13,7 @@ use winit::{
     application::ApplicationHandler,
     event::*,
     event_loop::{ActiveEventLoop, EventLoop},
+    keyboard::{KeyCode, PhysicalKey},
     window::{Window, WindowAttributes, WindowId},
 };
 
@@ -103,7 +104,7 @@ impl App<'_> {
 fn main() -> Result<(), Box<dyn Error>> {
     pretty_env_logger::init(); // Log to stderr (if you run with `RUST_LOG=debug`).
     let ines = remun::load_from_file(env::args().nth(1).ok_or("please give file as argument")?)?;
-    let state = remun::State::new(ines);
+    let state = remun::State::new(ines)?;
     let event_loop = EventLoop::new()?;
     // For alternative loop run options see `pump_events` and `run_on_demand` examples.
     //event_loop.run_app(App { window: None, render_state: None })?;
@@ -135,8 +136,7 @@ impl ApplicationHandler for App<'_> {
         });
 
         let window_wrapper = Arc::new(window);
-        let render_state =
-            pollster::block_on(RenderState::new(window_wrapper.clone(), &self.state.ines));
+        let render_state = pollster::block_on(RenderState::new(window_wrapper.clone()));
 
         // We use the egui_wgpu_backend crate as the render backend.
         let render_pass = RenderPass::new(&render_state.device, render_state.config.format, 1);
@@ -157,6 +157,9 @@ impl ApplicationHandler for App<'_> {
     }
     fn exiting(&mut self, event_loop: &ActiveEventLoop) {
         println!("EXITING (fr now)");
+        if let Err(e) = self.state.write_save() {
+            log::error!("failed to write the save file: {e}");
+        }
     }
     fn window_event(&mut self, event_loop: &ActiveEventLoop, _: WindowId, event: WindowEvent) {
         log::trace!("{event:?}");
@@ -202,7 +205,18 @@ impl ApplicationHandler for App<'_> {
             }
             WindowEvent::KeyboardInput { event, .. } => {
                 use winit::event::ElementState;
-                let winit::event::KeyEvent { text, state, .. } = event;
+                let winit::event::KeyEvent {
+                    text,
+                    state,
+                    physical_key,
+                    ..
+                } = event;
+                if let PhysicalKey::Code(code) = physical_key
+                    && let Some(button) = joypad_button(code)
+                {
+                    self.state
+                        .set_button(Port::One, button, state == ElementState::Pressed);
+                }
                 if let Some(text) = text
                     && text == "g"
                     && state == ElementState::Pressed
@@ -215,6 +229,22 @@ impl ApplicationHandler for App<'_> {
     }
 }
 
+/// Keyboard layout of the controller in port 1.
+fn joypad_button(code: KeyCode) -> Option<u8> {
+    use remun::controller::buttons;
+    Some(match code {
+        KeyCode::KeyX => buttons::A,
+        KeyCode::KeyZ => buttons::B,
+        KeyCode::ShiftRight => buttons::SELECT,
+        KeyCode::Enter => buttons::START,
+        KeyCode::ArrowUp => buttons::UP,
+        KeyCode::ArrowDown => buttons::DOWN,
+        KeyCode::ArrowLeft => buttons::LEFT,
+        KeyCode::ArrowRight => buttons::RIGHT,
+        _ => return None,
+    })
+}
+
 fn render(app: &mut App) -> Result<(), wgpu::SurfaceError> {
     let egui_overlay = app.egui_overlay.as_mut().unwrap();
     let start_time = &mut app.start_time;
@@ -275,7 +305,7 @@ fn render(app: &mut App) -> Result<(), wgpu::SurfaceError> {
             })],
             depth_stencil_attachment: None,
         });
-        nes_graphics.update_buffers(queue);
+        nes_graphics.update_buffers(queue, state);
         nes_graphics.draw(&mut render_pass);
     }
     // Egui render pass
@@ -328,7 +358,7 @@ impl RenderState<'_> {
             log::error!("trying to resize to a size 0");
         }
     }
-    pub async fn new(window: Arc<Window>, ines: &Ines) -> Self {
+    pub async fn new(window: Arc<Window>) -> Self {
         let width = window.inner_size().width;
         let height = window.inner_size().height;
         let size = window.inner_size();
@@ -396,7 +426,7 @@ impl RenderState<'_> {
             view_formats: vec![],
         };
 
-        let nes_graphics = nes_graphics::NesGraphics::new(&device, &queue, ines, &config);
+        let nes_graphics = nes_graphics::NesGraphics::new(&device, &config);
 
         Self {
             surface,
@@ -1,13 +1,8 @@
-// assumptions:
-// * same bind group
-// * 1 draw call
-// everything is stored in a consecutive buffer
+// Draws the PPU framebuffer as a texture over the whole window.
 
-use shared::Ines;
-use wgpu::{BufferDescriptor, Queue, RenderPass, util::DeviceExt};
-
-// f32 * vertex * quad * (nr_sprites + background + foreground)
-const VERTEX_BUFFER_SIZE: usize = 4 * 4 * 4 * (64 + 31 * 29);
+use remun::State;
+use remun::ppu;
+use wgpu::{Queue, RenderPass, util::DeviceExt};
 
 // index buffer always the same
 const SQUARE_INDICES: &[u16] = &[0, 1, 2, 2, 3, 0];
@@ -32,25 +27,11 @@ const WHOLE_SCREEN_VERTICES: &[Vertex] = &[
     },
 ];
 
-const TEST_2_VERT: &[Vertex] = &[
-    // top left
-    Vertex {
-        position: [-0.5, 0.5],
-        tex_coords: [0.0, 0.0],
-    },
-    Vertex {
-        position: [-0.5, -0.5],
-        tex_coords: [0.0, 1.0],
-    },
-    Vertex {
-        position: [0.5, -0.5],
-        tex_coords: [1.0, 1.0],
-    },
-    Vertex {
-        position: [0.5, 0.5],
-        tex_coords: [1.0, 0.0],
-    },
-];
+const SCREEN_SIZE: wgpu::Extent3d = wgpu::Extent3d {
+    width: ppu::WIDTH as u32,
+    height: ppu::HEIGHT as u32,
+    depth_or_array_layers: 1,
+};
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
@@ -74,12 +55,12 @@ impl Vertex {
 }
 
 pub struct NesGraphics {
-    // background, sprites, foreground
-    // everything drawn in order
     vertex_buffer: wgpu::Buffer,
     index_buffer: wgpu::Buffer,
     render_pipeline: wgpu::RenderPipeline,
     diffuse_bind_group: wgpu::BindGroup,
+    /// The framebuffer of the PPU, uploaded every frame.
+    screen_texture: wgpu::Texture,
 }
 impl NesGraphics {
     pub fn draw(&self, render_pass: &mut RenderPass) {
@@ -87,133 +68,52 @@ impl NesGraphics {
         render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
         render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
         render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-        render_pass.draw_indexed(0..(6 * (64 + 2)), 0, 0..1);
+        render_pass.draw_indexed(0..SQUARE_INDICES.len() as u32, 0, 0..1);
     }
 
-    pub fn update_buffers(&self, queue: &Queue) {
-        // clear buffer (unnecessary)
-        queue.write_buffer(&self.vertex_buffer, 0, &[0; (4 * 4) * 4 * (64 + 2)]);
-        // testing quads
-        queue.write_buffer(
-            &self.vertex_buffer,
-            0,
-            bytemuck::cast_slice(WHOLE_SCREEN_VERTICES),
-        );
-        queue.write_buffer(
-            &self.vertex_buffer,
-            4 * 4 * 6,
-            bytemuck::cast_slice(TEST_2_VERT),
+    /// Uploads the current PPU framebuffer.
+    pub fn update_buffers(&self, queue: &Queue, state: &State) {
+        queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                texture: &self.screen_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            &ppu::to_rgba(&state.ppu_state.framebuffer[..]),
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * ppu::WIDTH as u32),
+                rows_per_image: Some(ppu::HEIGHT as u32),
+            },
+            SCREEN_SIZE,
         );
     }
 
-    pub fn new(
-        device: &wgpu::Device,
-        queue: &Queue,
-        ines: &Ines,
-        config: &wgpu::SurfaceConfiguration,
-    ) -> Self {
-        // vertex buffer
-        // 4 * 4 bytes per vertex, 4 vertices per quad, 64 + 2 quads
-        let size = (4 * 4) * 4 * (64 + 2);
-        let vertex_buffer = device.create_buffer(&BufferDescriptor {
-            label: Some("gleeby goo"),
-            size,
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
+    pub fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Self {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Vertex Buffer"),
+            contents: bytemuck::cast_slice(WHOLE_SCREEN_VERTICES),
+            usage: wgpu::BufferUsages::VERTEX,
         });
-
-        // index buffer
-        let mut index_buffer_cpu: [u16; 6 * (64 + 2)] = [0; 6 * (64 + 2)];
-        [SQUARE_INDICES; 64 + 2]
-            .iter()
-            .enumerate()
-            .for_each(|(square_index, square_indices)| {
-                square_indices
-                    .iter()
-                    .enumerate()
-                    .for_each(|(index_index, vertex_index)| {
-                        index_buffer_cpu[square_index * 6 + index_index] =
-                            square_index as u16 * 3 + *vertex_index;
-                    });
-            });
         let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Index Buffer"),
-            contents: bytemuck::cast_slice(&index_buffer_cpu),
+            contents: bytemuck::cast_slice(SQUARE_INDICES),
             usage: wgpu::BufferUsages::INDEX,
         });
 
         // Textures
-        // the memory layout of the pattern tables: https://www.nesdev.org/wiki/PPU_pattern_tables
-        let chr_bank_start = shared::BANK_SIZE * (ines.inesprg as usize * 2);
-        let raw_texture = &ines.banks[chr_bank_start..chr_bank_start + shared::BANK_SIZE / 2];
-        let color_lookup: [u32; 4] = [0x000000FF, 0xeb3000ff, 0x2ADD00FF, 0x46fff4ff];
-        let mut diffuse_rgba: Vec<u8> = vec![0; 16 * 16 * 8 * 8 * 4];
-        raw_texture
-            .iter()
-            .array_chunks::<16>()
-            .enumerate()
-            .for_each(|(tile_index, tile)| {
-                // NOTE changes the texture so it conforms with the standard way of displaying pattern
-                // tables.
-                let tile_index =
-                    tile_index / 2 + (16 * (tile_index % 2)) + ((tile_index / 32) * 16);
-                for row in 0..8 {
-                    let mut b0 = *tile[row];
-                    let mut b1 = *tile[row + 8];
-                    // generate 8 colors
-                    for column in 0..8 {
-                        let rgba =
-                            color_lookup[((b0 & 1) | ((b1 & 1) << 1)) as usize].to_be_bytes();
-                        for (color_index, color) in rgba.iter().enumerate() {
-                            // the following offsets are in pixels
-                            let tile_x_offset = (tile_index % 16) * 8;
-                            let tile_y_offset = 16 * 8 * 8 * (tile_index / 16);
-                            let row_offset = row * 16 * 8 + 7;
-                            let index = (tile_x_offset + tile_y_offset + row_offset - column) * 4
-                                + color_index;
-                            diffuse_rgba[index] = *color;
-                        }
-                        b0 >>= 1;
-                        b1 >>= 1;
-                    }
-                }
-            });
-
-        let dimensions = (16 * 8, 16 * 8);
-        let texture_size = wgpu::Extent3d {
-            width: dimensions.0,
-            height: dimensions.1,
-            depth_or_array_layers: 1,
-        };
         let diffuse_texture = device.create_texture(&wgpu::TextureDescriptor {
-            size: texture_size,
-            mip_level_count: 1, // We'll talk about this a little later
+            size: SCREEN_SIZE,
+            mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format: wgpu::TextureFormat::Rgba8UnormSrgb,
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-            label: Some("diffuse_texture"),
+            label: Some("screen_texture"),
             view_formats: &[],
         });
 
-        // write texture to gpu
-        queue.write_texture(
-            wgpu::TexelCopyTextureInfo {
-                texture: &diffuse_texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            &diffuse_rgba,
-            //&diffuse_rgba,
-            wgpu::TexelCopyBufferLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * dimensions.0),
-                rows_per_image: Some(dimensions.1),
-            },
-            texture_size,
-        );
-
         let diffuse_texture_view =
             diffuse_texture.create_view(&wgpu::TextureViewDescriptor::default());
         let diffuse_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
@@ -317,6 +217,7 @@ impl NesGraphics {
             index_buffer,
             diffuse_bind_group,
             render_pipeline,
+            screen_texture: diffuse_texture,
         }
     }
 }
@@ -14,6 +14,7 @@ use egui::FontId;
 use egui::Key;
 use egui::Slider;
 use egui::TextStyle;
+use remun::ExecutionError;
 use remun::State;
 use rfd::FileDialog;
 use shared::AddressingMode::*;
@@ -35,6 +36,8 @@ pub struct Visualizer {
     view: View,
     debugger: Debugger,
     hex_editor: HexEditor,
+    /// Set when the CPU fails to run an instruction, halts execution.
+    error: Option<ExecutionError>,
 }
 
 enum View {
@@ -94,10 +97,15 @@ impl Visualizer {
             debugger: Debugger::new(state),
             hex_editor: HexEditor::new(),
             time_last_frame: Instant::now(),
+            error: None,
         }
     }
     fn run_one_instruction(&mut self, state: &mut State) {
-        state.run_one_instruction();
+        if let Err(e) = state.run_one_instruction() {
+            log::error!("{e}");
+            self.running = false;
+            self.error = Some(e);
+        }
         self.debugger.jump_to_pc(state);
     }
     pub fn update(&mut self, ctx: &egui::Context, state: &mut State) {
@@ -121,8 +129,14 @@ impl Visualizer {
                         .pick_file();
                     // just log the errors in the console!
                     if let Some(path) = path {
-                        match remun::load_from_file(path) {
-                            Ok(ines) => *state = State::new(ines),
+                        match remun::load_from_file(path).and_then(State::new) {
+                            Ok(new_state) => {
+                                if let Err(e) = state.write_save() {
+                                    log::error!("failed to write the save file: {e}");
+                                }
+                                *state = new_state;
+                                self.error = None;
+                            }
                             Err(e) => log::error!("{e}"),
                         }
                     } else {
@@ -131,9 +145,20 @@ impl Visualizer {
                 }
                 if ui.button("Soft Reset").clicked() {
                     state.reset();
+                    self.error = None;
                 }
                 if ui.button("Hard Reset").clicked() {
-                    *state = State::new(state.ines.clone());
+                    // The new state loads the save again
+                    if let Err(e) = state.write_save() {
+                        log::error!("failed to write the save file: {e}");
+                    }
+                    match State::new(state.ines.clone()) {
+                        Ok(new_state) => {
+                            *state = new_state;
+                            self.error = None;
+                        }
+                        Err(e) => log::error!("{e}"),
+                    }
                 }
                 if ui.small_button("step").clicked() {
                     self.run_one_instruction(state);
@@ -157,6 +182,9 @@ impl Visualizer {
                     for _ in 0..instructions_to_run {
                         self.run_one_instruction(state);
                         self.time_last_frame = Instant::now();
+                        if !self.running {
+                            break;
+                        }
                         if let Some(m) = state.ines.metadata.as_ref()
                             && m.breakpoints.contains(&state.pc)
                         {
@@ -165,6 +193,9 @@ impl Visualizer {
                         }
                     }
                 }
+                if let Some(e) = self.error.as_ref() {
+                    ui.colored_label(Color32::RED, format!("Halted: {e}"));
+                }
                 ui.monospace(format!("PC: ${:04X}", state.pc));
                 ui.monospace(format!("A: ${:02X}", state.a));
                 ui.monospace(format!("X: ${:02X}", state.x));
@@ -189,12 +220,23 @@ impl Visualizer {
                 show_flag!(C);
 
                 ui.label("PPU state");
-                if let Some(addr) = state.ppu_state.tmp_addr {
-                    ui.monospace(format!("temporary address: ${:04X}", addr));
-                } else {
-                    ui.monospace("temporary address: no!");
-                }
-                ui.monospace(format!("temporary value: ${:02X}", state.ppu_state.tmp_val));
+                ui.monospace(format!("v: ${:04X}", state.ppu_state.v));
+                ui.monospace(format!("t: ${:04X}", state.ppu_state.t));
+                ui.monospace(format!(
+                    "fine x: {} w: {}",
+                    state.ppu_state.x, state.ppu_state.w
+                ));
+                ui.monospace(format!(
+                    "ctrl: ${:02X} mask: ${:02X}",
+                    state.ppu_state.ctrl, state.ppu_state.mask
+                ));
+                ui.monospace(format!("read buffer: ${:02X}", state.ppu_state.tmp_val));
+                ui.monospace(format!(
+                    "scanline: {} dot: {}",
+                    state.ppu_state.scanline, state.ppu_state.dot
+                ));
+                ui.monospace(format!("frame: {}", state.ppu_state.frame));
+                ui.monospace(format!("mirroring: {:?}", state.ppu_state.mirroring));
 
                 //ui.image(egui::include_image!(
                 //    "../logo.png"
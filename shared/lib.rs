@@ -6,7 +6,7 @@ use std::{
     error::Error,
     fmt,
     fs::File,
-    io::{self, BufReader, Read},
+    io::{self, Read, Write},
     path::{Path, PathBuf},
     str::FromStr,
 };
@@ -19,24 +19,70 @@ use strum::IntoEnumIterator;
 /// for the banks in the assembler.
 pub const BANK_SIZE: usize = 1024 * 8;
 
-/// Representation of an iNES file (not NES 2.0 just yet :))
-#[derive(Clone)]
+/// Representation of an iNES 1.0 or NES 2.0 file,
+/// https://www.nesdev.org/wiki/NES_2.0
+#[derive(Clone, Default)]
 pub struct Ines {
     /// Size of PRG ROM in 16KiB units.
     pub inesprg: u16,
     /// Size of CHR ROM in 8KiB units.
     pub ineschr: u16,
-    /// Vertically mirrored (1), Horizontally mirrored (0).
+    /// Vertically mirrored (1), Horizontally mirrored (0), bit 3 is set
+    /// for four-screen VRAM, like the low bits of header byte 6.
     pub mirroring: u16,
     /// The iNES mapper index, does not fully describe the hardware.
     pub mapper: u16,
+    /// Variant of the mapper, NES 2.0 only.
+    pub submapper: u8,
+    /// The PRG-RAM at $6000-$7FFF is battery backed and should be saved.
+    pub battery: bool,
+    /// 512 bytes the cartridge has at $7000-$71FF.
+    pub trainer: Option<Vec<u8>>,
+    /// Volatile and battery backed PRG-RAM, in bytes.
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    /// Volatile and battery backed CHR-RAM, in bytes.
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub console: Console,
+    pub region: Region,
+    /// Number of miscellaneous ROMs in `misc_rom`, NES 2.0 only.
+    pub misc_roms: u8,
+    /// The default expansion device, NES 2.0 only.
+    pub expansion_device: u8,
+    /// The header was in NES 2.0 format.
+    pub nes2: bool,
     /// The rest of the iNES file, PRG first then CHR.
     pub banks: Vec<u8>,
+    /// Whatever follows the CHR data, when `misc_roms` is set.
+    pub misc_rom: Vec<u8>,
 
     // Metadata
     pub metadata: Option<InesMetadata>,
 }
 
+/// The kind of console the cartridge is made for.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Console {
+    #[default]
+    Nes,
+    VsSystem,
+    Playchoice10,
+    /// One of the NES 2.0 extended console types.
+    Extended(u8),
+}
+
+/// The CPU/PPU timing the cartridge expects.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+    /// Runs on both.
+    Multiple,
+    Dendy,
+}
+
 /// Contains debug info.
 #[derive(Clone, Debug, Deserialize, Serialize, Default)]
 pub struct InesMetadata {
@@ -55,10 +101,36 @@ pub enum InesError {
 }
 
 /// Error when parsing an INES file.
-#[derive(Debug, strum_macros::Display)]
+#[derive(Debug, PartialEq, Eq)]
 pub enum InesParseError {
+    /// The file does not start with "NES" and $1A.
     InvalidHeader,
+    /// The data does not match the sizes in the header.
     FileInvalidLength,
+    /// There is no PRG-ROM.
+    NoPrgRom,
+    /// A NES 2.0 ROM size that is not a whole number of banks, or too large.
+    UnsupportedRomSize,
+    /// NES 2.0 battery-backed RAM sizes are given but the battery bit is clear.
+    NvramWithoutBattery,
+}
+
+impl fmt::Display for InesParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InesParseError::InvalidHeader => write!(f, "not an iNES file, invalid header"),
+            InesParseError::FileInvalidLength => {
+                write!(f, "file size does not match the ROM sizes in the header")
+            }
+            InesParseError::NoPrgRom => write!(f, "the header has no PRG-ROM"),
+            InesParseError::UnsupportedRomSize => {
+                write!(f, "ROM size is not a whole number of banks")
+            }
+            InesParseError::NvramWithoutBattery => {
+                write!(f, "battery-backed RAM without the battery bit set")
+            }
+        }
+    }
 }
 
 impl From<io::Error> for InesError {
@@ -92,42 +164,261 @@ impl fmt::Display for InesError {
 
 impl std::error::Error for InesError {}
 
+/// Size of the header in bytes.
+pub const HEADER_SIZE: usize = 16;
+/// Size of a trainer in bytes.
+pub const TRAINER_SIZE: usize = 512;
+const PRG_UNIT: usize = 1024 * 16;
+const CHR_UNIT: usize = 1024 * 8;
+
 impl Ines {
     /// Reads INES file.
     pub fn from_file<T: AsRef<Path>>(path: T) -> Result<Self, InesError> {
-        let f = File::open(&path)?;
-        let mut reader = BufReader::new(f);
-        let mut header: [u8; 16] = [0; 16];
-        reader.read_exact(&mut header)?;
-        if &header[0..4] != b"NES\x1a" {
-            return Err(InesError::ParseError(InesParseError::InvalidHeader));
+        let mut bytes = Vec::new();
+        File::open(&path)?.read_to_end(&mut bytes)?;
+        let mut ines = Self::from_bytes(&bytes)?;
+        ines.metadata = Some(InesMetadata {
+            data_source: Some(PathBuf::from(path.as_ref())),
+            labels: HashMap::new(),
+            breakpoints: HashSet::new(),
+        });
+        Ok(ines)
+    }
+
+    /// Parses an iNES 1.0 or NES 2.0 file.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, InesParseError> {
+        if !bytes.starts_with(b"NES\x1a") {
+            return Err(InesParseError::InvalidHeader);
+        }
+        let header = bytes
+            .get(..HEADER_SIZE)
+            .ok_or(InesParseError::FileInvalidLength)?;
+        let nes2 = header[7] & 0x0C == 0x08;
+        // Old tools wrote their name over bytes 7-15, like "DiskDude!"
+        let archaic = !nes2 && (header[7] & 0x0C != 0 || header[12..].iter().any(|b| *b != 0));
+        let flags7 = if archaic { 0 } else { header[7] };
+        let battery = header[6] & 0b10 != 0;
+        let mut ines = Ines {
+            mirroring: (header[6] & 0b1001) as u16,
+            mapper: ((header[6] >> 4) | (flags7 & 0xF0)) as u16,
+            battery,
+            console: match flags7 & 0b11 {
+                0 => Console::Nes,
+                1 => Console::VsSystem,
+                2 => Console::Playchoice10,
+                _ if nes2 => Console::Extended(header[13] & 0x0F),
+                _ => Console::Nes,
+            },
+            nes2,
+            ..Default::default()
+        };
+        if nes2 {
+            ines.mapper |= ((header[8] & 0x0F) as u16) << 8;
+            ines.submapper = header[8] >> 4;
+            ines.inesprg = rom_size(header[4], header[9] & 0x0F, PRG_UNIT)?;
+            ines.ineschr = rom_size(header[5], header[9] >> 4, CHR_UNIT)?;
+            let ram_size = |shift: u8| if shift == 0 { 0 } else { 64 << shift };
+            ines.prg_ram_size = ram_size(header[10] & 0x0F);
+            ines.prg_nvram_size = ram_size(header[10] >> 4);
+            ines.chr_ram_size = ram_size(header[11] & 0x0F);
+            ines.chr_nvram_size = ram_size(header[11] >> 4);
+            if !battery && ines.prg_nvram_size + ines.chr_nvram_size != 0 {
+                return Err(InesParseError::NvramWithoutBattery);
+            }
+            ines.region = match header[12] & 0b11 {
+                0 => Region::Ntsc,
+                1 => Region::Pal,
+                2 => Region::Multiple,
+                _ => Region::Dendy,
+            };
+            ines.misc_roms = header[14] & 0b11;
+            ines.expansion_device = header[15] & 0x3F;
+        } else {
+            ines.inesprg = header[4] as u16;
+            ines.ineschr = header[5] as u16;
+            let prg_ram_banks = if archaic { 0 } else { header[8] };
+            ines.set_ines1_ram_sizes(prg_ram_banks);
+            if !archaic && header[9] & 1 != 0 {
+                ines.region = Region::Pal;
+            }
+        }
+        if ines.inesprg == 0 {
+            return Err(InesParseError::NoPrgRom);
+        }
+
+        let mut data = &bytes[HEADER_SIZE..];
+        if header[6] & 0b100 != 0 {
+            let trainer = data
+                .get(..TRAINER_SIZE)
+                .ok_or(InesParseError::FileInvalidLength)?;
+            ines.trainer = Some(trainer.to_vec());
+            data = &data[TRAINER_SIZE..];
+        }
+        let rom_size = ines.inesprg as usize * PRG_UNIT + ines.ineschr as usize * CHR_UNIT;
+        if data.len() < rom_size || (data.len() > rom_size && ines.misc_roms == 0) {
+            return Err(InesParseError::FileInvalidLength);
+        }
+        ines.banks = data[..rom_size].to_vec();
+        ines.misc_rom = data[rom_size..].to_vec();
+        Ok(ines)
+    }
+
+    /// Fills in the RAM sizes iNES 1.0 leaves out: `prg_ram_banks` 8KiB
+    /// banks of PRG-RAM (0 counts as 1), battery backed when `battery` is
+    /// set, and 8KiB of CHR-RAM when there is no CHR-ROM.
+    pub fn set_ines1_ram_sizes(&mut self, prg_ram_banks: u8) {
+        let prg_ram = prg_ram_banks.max(1) as usize * CHR_UNIT;
+        (self.prg_ram_size, self.prg_nvram_size) = if self.battery {
+            (0, prg_ram)
+        } else {
+            (prg_ram, 0)
+        };
+        self.chr_ram_size = if self.ineschr == 0 { CHR_UNIT } else { 0 };
+        self.chr_nvram_size = 0;
+    }
+
+    /// Can the header be written as iNES 1.0 without losing anything?
+    fn fits_ines1(&self) -> bool {
+        let prg_ram = self.prg_ram_size + self.prg_nvram_size;
+        // All of it is battery backed or none
+        let one_kind = if self.battery {
+            self.prg_ram_size == 0
+        } else {
+            self.prg_nvram_size == 0
+        };
+        let chr_ram = if self.ineschr == 0 { CHR_UNIT } else { 0 };
+        self.mapper <= 0xFF
+            && self.submapper == 0
+            && self.inesprg <= 0xFF
+            && self.ineschr <= 0xFF
+            && matches!(self.region, Region::Ntsc | Region::Pal)
+            && !matches!(self.console, Console::Extended(_))
+            && self.misc_roms == 0
+            && prg_ram != 0
+            && prg_ram.is_multiple_of(CHR_UNIT)
+            && prg_ram / CHR_UNIT <= 0xFF
+            && one_kind
+            && self.chr_ram_size == chr_ram
+            && self.chr_nvram_size == 0
+    }
+
+    /// Serializes to a .nes file, as NES 2.0 when `nes2` is set or the
+    /// header does not fit iNES 1.0. PRG and CHR are padded with zeros to
+    /// the sizes in the header.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, InesParseError> {
+        let nes2 = self.nes2 || !self.fits_ines1();
+        let mut header = [0u8; HEADER_SIZE];
+        header[..4].copy_from_slice(b"NES\x1a");
+        header[6] = (self.mapper as u8) << 4
+            | (self.mirroring as u8 & 0b1001)
+            | (self.battery as u8) << 1
+            | (self.trainer.is_some() as u8) << 2;
+        header[7] = (self.mapper as u8 & 0xF0)
+            | match self.console {
+                Console::Nes => 0,
+                Console::VsSystem => 1,
+                Console::Playchoice10 => 2,
+                Console::Extended(_) => 3,
+            };
+        if nes2 {
+            let (prg_lsb, prg_msb) = encode_rom_size(self.inesprg, PRG_UNIT)?;
+            let (chr_lsb, chr_msb) = encode_rom_size(self.ineschr, CHR_UNIT)?;
+            header[4] = prg_lsb;
+            header[5] = chr_lsb;
+            header[7] |= 0x08;
+            header[8] = (self.mapper >> 8) as u8 & 0x0F | self.submapper << 4;
+            header[9] = prg_msb | chr_msb << 4;
+            header[10] = ram_shift(self.prg_ram_size) | ram_shift(self.prg_nvram_size) << 4;
+            header[11] = ram_shift(self.chr_ram_size) | ram_shift(self.chr_nvram_size) << 4;
+            header[12] = match self.region {
+                Region::Ntsc => 0,
+                Region::Pal => 1,
+                Region::Multiple => 2,
+                Region::Dendy => 3,
+            };
+            if let Console::Extended(console) = self.console {
+                header[13] = console & 0x0F;
+            }
+            header[14] = self.misc_roms & 0b11;
+            header[15] = self.expansion_device & 0x3F;
+        } else {
+            header[4] = self.inesprg as u8;
+            header[5] = self.ineschr as u8;
+            // 0 means the usual 8KiB
+            let prg_ram_banks = (self.prg_ram_size + self.prg_nvram_size).div_ceil(CHR_UNIT);
+            header[8] = if prg_ram_banks > 1 {
+                prg_ram_banks.min(0xFF) as u8
+            } else {
+                0
+            };
+            header[9] = (self.region == Region::Pal) as u8;
+        }
+
+        let rom_size = self.inesprg as usize * PRG_UNIT + self.ineschr as usize * CHR_UNIT;
+        let mut bytes = header.to_vec();
+        if let Some(trainer) = self.trainer.as_ref() {
+            let start = bytes.len();
+            bytes.extend(trainer.iter().take(TRAINER_SIZE));
+            bytes.resize(start + TRAINER_SIZE, 0);
         }
-        let inesprg = header[4] as u16;
-        let ineschr = header[5] as u16;
-        let mirroring = (header[6] & 1) as u16;
-        let mapper = (header[6] >> 4) as u16;
-        let mut banks = Vec::new();
-        let data_len = reader.read_to_end(&mut banks)?;
-        let expected_size = inesprg as usize * 1024 * 16 + ineschr as usize * 1024 * 8;
-        if data_len != expected_size {
-            return Err(InesError::ParseError(InesParseError::FileInvalidLength));
+        let start = bytes.len();
+        bytes.extend(self.banks.iter().take(rom_size));
+        bytes.resize(start + rom_size, 0);
+        if self.misc_roms != 0 {
+            bytes.extend(&self.misc_rom);
         }
-        let data_source: Option<PathBuf> = Some(PathBuf::from(path.as_ref()));
-        Ok(Ines {
-            inesprg,
-            ineschr,
-            mirroring,
-            mapper,
-            banks,
-            metadata: Some(InesMetadata {
-                data_source,
-                labels: HashMap::new(),
-                breakpoints: HashSet::new(),
-            }),
-        })
+        Ok(bytes)
+    }
+
+    /// Writes a .nes file, see `to_bytes`.
+    pub fn write_to_file<T: AsRef<Path>>(&self, path: T) -> Result<(), InesError> {
+        let bytes = self.to_bytes()?;
+        File::create(path)?.write_all(&bytes)?;
+        Ok(())
     }
 }
 
+/// The NES 2.0 size byte and upper nibble for `size` `unit`s, in the
+/// exponent form when it does not fit in 12 bits.
+fn encode_rom_size(size: u16, unit: usize) -> Result<(u8, u8), InesParseError> {
+    if size < 0xF00 {
+        return Ok((size as u8, (size >> 8) as u8));
+    }
+    let bytes = size as usize * unit;
+    let exponent = bytes.trailing_zeros();
+    let multiplier = bytes >> exponent;
+    if exponent > 0x3F || multiplier > 7 {
+        return Err(InesParseError::UnsupportedRomSize);
+    }
+    Ok((((exponent as u8) << 2) | ((multiplier as u8 - 1) / 2), 0x0F))
+}
+
+/// The NES 2.0 shift count for a RAM size, rounded up to a power of two.
+fn ram_shift(size: usize) -> u8 {
+    if size == 0 {
+        0
+    } else {
+        ((size.next_power_of_two() >> 6).max(2).trailing_zeros() as u8).min(15)
+    }
+}
+
+/// A NES 2.0 ROM size in `unit`s, from the size byte and the upper nibble
+/// in byte 9. An upper nibble of $F means 2^E * (M * 2 + 1) bytes.
+fn rom_size(lsb: u8, msb: u8, unit: usize) -> Result<u16, InesParseError> {
+    if msb != 0x0F {
+        return Ok(u16::from(msb) << 8 | u16::from(lsb));
+    }
+    let multiplier = (lsb & 0b11) as usize * 2 + 1;
+    let bytes = 1usize
+        .checked_shl((lsb >> 2) as u32)
+        .and_then(|size| size.checked_mul(multiplier))
+        .ok_or(InesParseError::UnsupportedRomSize)?;
+    if bytes % unit != 0 {
+        return Err(InesParseError::UnsupportedRomSize);
+    }
+    u16::try_from(bytes / unit).map_err(|_| InesParseError::UnsupportedRomSize)
+}
+
 /// Addressing modes
 #[allow(non_snake_case)]
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -185,7 +476,8 @@ pub mod flags {
     pub const N: u8 = 1 << 7;
     /// Overflow
     pub const V: u8 = 1 << 6;
-    // Bit 5 unused :(
+    /// Unused, always reads as set
+    pub const U: u8 = 1 << 5;
     /// Break
     pub const B: u8 = 1 << 4;
     /// Decimal mode (unused)
@@ -225,7 +517,8 @@ impl AddressingMode {
             AddressingMode::IND => 3,
             AddressingMode::X_IND => 2,
             AddressingMode::IND_Y => 2,
-            AddressingMode::J => unimplemented!("length of illegal instruction!"),
+            // Jams the CPU, only the opcode byte is ever read
+            AddressingMode::J => 1,
         }
     }
 }
@@ -236,18 +529,13 @@ impl FromStr for Opcode {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         CODEPOINTS
             .iter()
-            .find_map(
-                |Codepoint {
-                     opcode,
-                     addressing_mode: _,
-                 }| {
-                    if format!("{}", opcode) == s {
-                        Some(opcode)
-                    } else {
-                        None
-                    }
-                },
-            )
+            .find_map(|Codepoint { opcode, .. }| {
+                if format!("{}", opcode) == s {
+                    Some(opcode)
+                } else {
+                    None
+                }
+            })
             .cloned()
             .ok_or(())
     }
@@ -394,6 +682,18 @@ pub enum Opcode {
     JAM,
 }
 
+impl Opcode {
+    /// Does this opcode take an extra cycle when an indexed addressing mode
+    /// crosses a page boundary? Only true for opcodes that just read memory,
+    /// writes and read-modify-writes always spend the extra cycle.
+    pub fn page_cross_penalty(&self) -> bool {
+        matches!(
+            self,
+            ADC | AND | CMP | EOR | LDA | LDX | LDY | ORA | SBC | LAX | LAS | NOP
+        )
+    }
+}
+
 impl From<u8> for Opcode {
     fn from(value: u8) -> Self {
         CODEPOINTS[value as usize].opcode.clone()
@@ -412,6 +712,7 @@ pub fn opcode_addressing_modes(o: &Opcode) -> Vec<AddressingMode> {
             |Codepoint {
                  opcode,
                  addressing_mode,
+                 ..
              }| {
                 if *o == *opcode {
                     Some(addressing_mode)
@@ -430,32 +731,33 @@ pub fn opcode_addressing_modes(o: &Opcode) -> Vec<AddressingMode> {
 pub struct Codepoint {
     pub opcode: Opcode,
     pub addressing_mode: AddressingMode,
-    //cycles: u8,
+    /// Base number of cycles, not counting page crossings or taken branches.
+    pub cycles: u8,
 }
 
 macro_rules! tabalize {
-    ($($x:expr,$y:expr ); *;) => {
-        [ $(Codepoint { opcode: $x, addressing_mode: $y },)* ]
+    ($($x:expr,$y:expr,$c:expr ); *;) => {
+        [ $(Codepoint { opcode: $x, addressing_mode: $y, cycles: $c },)* ]
     };
 }
 
 // Zoom out to see properly :)
 /// The entire 6502 instuction set.
 pub const CODEPOINTS: [Codepoint; 256] = tabalize! [
-    BRK,IMPL ; ORA,X_IND ; JAM,J   ; SLO,X_IND ; NOP,ZPG   ; ORA,ZPG   ; ASL,ZPG   ; SLO,ZPG   ; PHP,IMPL ; ORA,IMM   ; ASL,A    ; ANC,IMM   ; NOP,ABS   ; ORA,ABS   ; ASL,ABS   ; SLO,ABS   ;
-    BPL,REL  ; ORA,IND_Y ; JAM,J   ; SLO,IND_Y ; NOP,ZPG_X ; ORA,ZPG_X ; ASL,ZPG_X ; SLO,ZPG_X ; CLC,IMPL ; ORA,ABS_Y ; NOP,IMPL ; SLO,ABS_Y ; NOP,ABS_X ; ORA,ABS_X ; ASL,ABS_X ; SLO,ABS_X ;
-    JSR,ABS  ; AND,X_IND ; JAM,J   ; RLA,X_IND ; BIT,ZPG   ; AND,ZPG   ; ROL,ZPG   ; RLA,ZPG   ; PLP,IMPL ; AND,IMM   ; ROL,A    ; ANC,IMM   ; BIT,ABS   ; AND,ABS   ; ROL,ABS   ; RLA,ABS   ;
-    BMI,REL  ; AND,IND_Y ; JAM,J   ; RLA,IND_Y ; NOP,ZPG_X ; AND,ZPG_X ; ROL,ZPG_X ; RLA,ZPG_X ; SEC,IMPL ; AND,ABS_Y ; NOP,IMPL ; RLA,ABS_Y ; NOP,ABS_X ; AND,ABS_X ; ROL,ABS_X ; RLA,ABS_X ;
-    RTI,IMPL ; EOR,X_IND ; JAM,J   ; SRE,X_IND ; NOP,ZPG   ; EOR,ZPG   ; LSR,ZPG   ; SRE,ZPG   ; PHA,IMPL ; EOR,IMM   ; LSR,A    ; ALR,IMM   ; JMP,ABS   ; EOR,ABS   ; LSR,ABS   ; SRE,ABS   ;
-    BVC,REL  ; EOR,IND_Y ; JAM,J   ; SRE,IND_Y ; NOP,ZPG_X ; EOR,ZPG_X ; LSR,ZPG_X ; SRE,ZPG_X ; CLI,IMPL ; EOR,ABS_Y ; NOP,IMPL ; SRE,ABS_Y ; NOP,ABS_X ; EOR,ABS_X ; LSR,ABS_X ; SRE,ABS_X ;
-    RTS,IMPL ; ADC,X_IND ; JAM,J   ; RRA,X_IND ; NOP,ZPG   ; ADC,ZPG   ; ROR,ZPG   ; RRA,ZPG   ; PLA,IMPL ; ADC,IMM   ; ROR,A    ; ARR,IMM   ; JMP,IND   ; ADC,ABS   ; ROR,ABS   ; RRA,ABS   ;
-    BVS,REL  ; ADC,IND_Y ; JAM,J   ; RRA,IND_Y ; NOP,ZPG_X ; ADC,ZPG_X ; ROR,ZPG_X ; RRA,ZPG_X ; SEI,IMPL ; ADC,ABS_Y ; NOP,IMPL ; RRA,ABS_Y ; NOP,ABS_X ; ADC,ABS_X ; ROR,ABS_X ; RRA,ABS_X ;
-    NOP,IMM  ; STA,X_IND ; NOP,IMM ; SAX,X_IND ; STY,ZPG   ; STA,ZPG   ; STX,ZPG   ; SAX,ZPG   ; DEY,IMPL ; NOP,IMM   ; TXA,IMPL ; ANE,IMM   ; STY,ABS   ; STA,ABS   ; STX,ABS   ; SAX,ABS   ;
-    BCC,REL  ; STA,IND_Y ; JAM,J   ; SHA,IND_Y ; STY,ZPG_X ; STA,ZPG_X ; STX,ZPG_Y ; SAX,ZPG_Y ; TYA,IMPL ; STA,ABS_Y ; TXS,IMPL ; TAS,ABS_Y ; SHY,ABS_X ; STA,ABS_X ; SHX,ABS_Y ; SHA,ABS_Y ;
-    LDY,IMM  ; LDA,X_IND ; LDX,IMM ; LAX,X_IND ; LDY,ZPG   ; LDA,ZPG   ; LDX,ZPG   ; LAX,ZPG   ; TAY,IMPL ; LDA,IMM   ; TAX,IMPL ; LXA,IMM   ; LDY,ABS   ; LDA,ABS   ; LDX,ABS   ; LAX,ABS   ;
-    BCS,REL  ; LDA,IND_Y ; JAM,J   ; LAX,IND_Y ; LDY,ZPG_X ; LDA,ZPG_X ; LDX,ZPG_Y ; LAX,ZPG_Y ; CLV,IMPL ; LDA,ABS_Y ; TSX,IMPL ; LAS,ABS_Y ; LDY,ABS_X ; LDA,ABS_X ; LDX,ABS_Y ; LAX,ABS_Y ;
-    CPY,IMM  ; CMP,X_IND ; NOP,IMM ; DCP,X_IND ; CPY,ZPG   ; CMP,ZPG   ; DEC,ZPG   ; DCP,ZPG   ; INY,IMPL ; CMP,IMM   ; DEX,IMPL ; SBX,IMM   ; CPY,ABS   ; CMP,ABS   ; DEC,ABS   ; DCP,ABS   ;
-    BNE,REL  ; CMP,IND_Y ; JAM,J   ; DCP,IND_Y ; NOP,ZPG_X ; CMP,ZPG_X ; DEC,ZPG_X ; DCP,ZPG_X ; CLD,IMPL ; CMP,ABS_Y ; NOP,IMPL ; DCP,ABS_Y ; NOP,ABS_X ; CMP,ABS_X ; DEC,ABS_X ; DCP,ABS_X ;
-    CPX,IMM  ; SBC,X_IND ; NOP,IMM ; ISC,X_IND ; CPX,ZPG   ; SBC,ZPG   ; INC,ZPG   ; ISC,ZPG   ; INX,IMPL ; SBC,IMM   ; NOP,IMPL ; USB, IMM  ; CPX,ABS   ; SBC,ABS   ; INC,ABS   ; ISC,ABS   ;
-    BEQ,REL  ; SBC,IND_Y ; JAM,J   ; ISC,IND_Y ; NOP,ZPG_X ; SBC,ZPG_X ; INC,ZPG_X ; ISC,ZPG_X ; SED,IMPL ; SBC,ABS_Y ; NOP,IMPL ; ISC,ABS_Y ; NOP,ABS_X ; SBC,ABS_X ; INC,ABS_X ; ISC,ABS_X ;
+    BRK,IMPL,7 ; ORA,X_IND,6 ; JAM,J,0   ; SLO,X_IND,8 ; NOP,ZPG,3   ; ORA,ZPG,3   ; ASL,ZPG,5   ; SLO,ZPG,5   ; PHP,IMPL,3 ; ORA,IMM,2   ; ASL,A,2    ; ANC,IMM,2   ; NOP,ABS,4   ; ORA,ABS,4   ; ASL,ABS,6   ; SLO,ABS,6 ;
+    BPL,REL,2  ; ORA,IND_Y,5 ; JAM,J,0   ; SLO,IND_Y,8 ; NOP,ZPG_X,4 ; ORA,ZPG_X,4 ; ASL,ZPG_X,6 ; SLO,ZPG_X,6 ; CLC,IMPL,2 ; ORA,ABS_Y,4 ; NOP,IMPL,2 ; SLO,ABS_Y,7 ; NOP,ABS_X,4 ; ORA,ABS_X,4 ; ASL,ABS_X,7 ; SLO,ABS_X,7 ;
+    JSR,ABS,6  ; AND,X_IND,6 ; JAM,J,0   ; RLA,X_IND,8 ; BIT,ZPG,3   ; AND,ZPG,3   ; ROL,ZPG,5   ; RLA,ZPG,5   ; PLP,IMPL,4 ; AND,IMM,2   ; ROL,A,2    ; ANC,IMM,2   ; BIT,ABS,4   ; AND,ABS,4   ; ROL,ABS,6   ; RLA,ABS,6 ;
+    BMI,REL,2  ; AND,IND_Y,5 ; JAM,J,0   ; RLA,IND_Y,8 ; NOP,ZPG_X,4 ; AND,ZPG_X,4 ; ROL,ZPG_X,6 ; RLA,ZPG_X,6 ; SEC,IMPL,2 ; AND,ABS_Y,4 ; NOP,IMPL,2 ; RLA,ABS_Y,7 ; NOP,ABS_X,4 ; AND,ABS_X,4 ; ROL,ABS_X,7 ; RLA,ABS_X,7 ;
+    RTI,IMPL,6 ; EOR,X_IND,6 ; JAM,J,0   ; SRE,X_IND,8 ; NOP,ZPG,3   ; EOR,ZPG,3   ; LSR,ZPG,5   ; SRE,ZPG,5   ; PHA,IMPL,3 ; EOR,IMM,2   ; LSR,A,2    ; ALR,IMM,2   ; JMP,ABS,3   ; EOR,ABS,4   ; LSR,ABS,6   ; SRE,ABS,6 ;
+    BVC,REL,2  ; EOR,IND_Y,5 ; JAM,J,0   ; SRE,IND_Y,8 ; NOP,ZPG_X,4 ; EOR,ZPG_X,4 ; LSR,ZPG_X,6 ; SRE,ZPG_X,6 ; CLI,IMPL,2 ; EOR,ABS_Y,4 ; NOP,IMPL,2 ; SRE,ABS_Y,7 ; NOP,ABS_X,4 ; EOR,ABS_X,4 ; LSR,ABS_X,7 ; SRE,ABS_X,7 ;
+    RTS,IMPL,6 ; ADC,X_IND,6 ; JAM,J,0   ; RRA,X_IND,8 ; NOP,ZPG,3   ; ADC,ZPG,3   ; ROR,ZPG,5   ; RRA,ZPG,5   ; PLA,IMPL,4 ; ADC,IMM,2   ; ROR,A,2    ; ARR,IMM,2   ; JMP,IND,5   ; ADC,ABS,4   ; ROR,ABS,6   ; RRA,ABS,6 ;
+    BVS,REL,2  ; ADC,IND_Y,5 ; JAM,J,0   ; RRA,IND_Y,8 ; NOP,ZPG_X,4 ; ADC,ZPG_X,4 ; ROR,ZPG_X,6 ; RRA,ZPG_X,6 ; SEI,IMPL,2 ; ADC,ABS_Y,4 ; NOP,IMPL,2 ; RRA,ABS_Y,7 ; NOP,ABS_X,4 ; ADC,ABS_X,4 ; ROR,ABS_X,7 ; RRA,ABS_X,7 ;
+    NOP,IMM,2  ; STA,X_IND,6 ; NOP,IMM,2 ; SAX,X_IND,6 ; STY,ZPG,3   ; STA,ZPG,3   ; STX,ZPG,3   ; SAX,ZPG,3   ; DEY,IMPL,2 ; NOP,IMM,2   ; TXA,IMPL,2 ; ANE,IMM,2   ; STY,ABS,4   ; STA,ABS,4   ; STX,ABS,4   ; SAX,ABS,4 ;
+    BCC,REL,2  ; STA,IND_Y,6 ; JAM,J,0   ; SHA,IND_Y,6 ; STY,ZPG_X,4 ; STA,ZPG_X,4 ; STX,ZPG_Y,4 ; SAX,ZPG_Y,4 ; TYA,IMPL,2 ; STA,ABS_Y,5 ; TXS,IMPL,2 ; TAS,ABS_Y,5 ; SHY,ABS_X,5 ; STA,ABS_X,5 ; SHX,ABS_Y,5 ; SHA,ABS_Y,5 ;
+    LDY,IMM,2  ; LDA,X_IND,6 ; LDX,IMM,2 ; LAX,X_IND,6 ; LDY,ZPG,3   ; LDA,ZPG,3   ; LDX,ZPG,3   ; LAX,ZPG,3   ; TAY,IMPL,2 ; LDA,IMM,2   ; TAX,IMPL,2 ; LXA,IMM,2   ; LDY,ABS,4   ; LDA,ABS,4   ; LDX,ABS,4   ; LAX,ABS,4 ;
+    BCS,REL,2  ; LDA,IND_Y,5 ; JAM,J,0   ; LAX,IND_Y,5 ; LDY,ZPG_X,4 ; LDA,ZPG_X,4 ; LDX,ZPG_Y,4 ; LAX,ZPG_Y,4 ; CLV,IMPL,2 ; LDA,ABS_Y,4 ; TSX,IMPL,2 ; LAS,ABS_Y,4 ; LDY,ABS_X,4 ; LDA,ABS_X,4 ; LDX,ABS_Y,4 ; LAX,ABS_Y,4 ;
+    CPY,IMM,2  ; CMP,X_IND,6 ; NOP,IMM,2 ; DCP,X_IND,8 ; CPY,ZPG,3   ; CMP,ZPG,3   ; DEC,ZPG,5   ; DCP,ZPG,5   ; INY,IMPL,2 ; CMP,IMM,2   ; DEX,IMPL,2 ; SBX,IMM,2   ; CPY,ABS,4   ; CMP,ABS,4   ; DEC,ABS,6   ; DCP,ABS,6 ;
+    BNE,REL,2  ; CMP,IND_Y,5 ; JAM,J,0   ; DCP,IND_Y,8 ; NOP,ZPG_X,4 ; CMP,ZPG_X,4 ; DEC,ZPG_X,6 ; DCP,ZPG_X,6 ; CLD,IMPL,2 ; CMP,ABS_Y,4 ; NOP,IMPL,2 ; DCP,ABS_Y,7 ; NOP,ABS_X,4 ; CMP,ABS_X,4 ; DEC,ABS_X,7 ; DCP,ABS_X,7 ;
+    CPX,IMM,2  ; SBC,X_IND,6 ; NOP,IMM,2 ; ISC,X_IND,8 ; CPX,ZPG,3   ; SBC,ZPG,3   ; INC,ZPG,5   ; ISC,ZPG,5   ; INX,IMPL,2 ; SBC,IMM,2   ; NOP,IMPL,2 ; USB,IMM,2   ; CPX,ABS,4   ; SBC,ABS,4   ; INC,ABS,6   ; ISC,ABS,6 ;
+    BEQ,REL,2  ; SBC,IND_Y,5 ; JAM,J,0   ; ISC,IND_Y,8 ; NOP,ZPG_X,4 ; SBC,ZPG_X,4 ; INC,ZPG_X,6 ; ISC,ZPG_X,6 ; SED,IMPL,2 ; SBC,ABS_Y,4 ; NOP,IMPL,2 ; ISC,ABS_Y,7 ; NOP,ABS_X,4 ; SBC,ABS_X,4 ; INC,ABS_X,7 ; ISC,ABS_X,7 ;
 ];
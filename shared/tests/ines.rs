@@ -0,0 +1,172 @@
+#[cfg(test)]
+mod test_ines {
+    use shared::{Console, Ines, InesParseError, Region};
+
+    /// A header followed by zeroed PRG and CHR of the given sizes.
+    fn file(header: [u8; 16], prg: usize, chr: usize) -> Vec<u8> {
+        let mut bytes = header.to_vec();
+        bytes.resize(16 + prg * 0x4000 + chr * 0x2000, 0);
+        bytes
+    }
+
+    #[test]
+    fn test_ines1() {
+        let header = *b"NES\x1a\x02\x01\x13\x40\x00\x00\x00\x00\x00\x00\x00\x00";
+        let ines = Ines::from_bytes(&file(header, 2, 1)).unwrap();
+        assert!(!ines.nes2);
+        assert_eq!((ines.inesprg, ines.ineschr), (2, 1));
+        assert_eq!(ines.mapper, 0x41);
+        assert_eq!(ines.mirroring, 1);
+        assert!(ines.battery);
+        // iNES 1.0 assumes 8KiB of PRG-RAM, battery backed here
+        assert_eq!((ines.prg_ram_size, ines.prg_nvram_size), (0, 0x2000));
+        assert_eq!(ines.chr_ram_size, 0);
+        assert_eq!(ines.banks.len(), 2 * 0x4000 + 0x2000);
+    }
+
+    #[test]
+    fn test_garbage_in_old_headers() {
+        let header = *b"NES\x1a\x01\x00\x10DiskDude!";
+        let ines = Ines::from_bytes(&file(header, 1, 0)).unwrap();
+        // the upper mapper nibble is ignored
+        assert_eq!(ines.mapper, 1);
+        assert_eq!(ines.chr_ram_size, 0x2000);
+    }
+
+    #[test]
+    fn test_nes2() {
+        let mut header = *b"NES\x1a\x00\x00\x0E\x19\x12\x01\x07\x97\x01\x00\x00\x05";
+        header[4] = 0x20;
+        let mut bytes = file(header, 0x120, 0);
+        // a trainer before the PRG
+        bytes.splice(16..16, [0xAA; 512]);
+        let ines = Ines::from_bytes(&bytes).unwrap();
+        assert!(ines.nes2);
+        assert_eq!(ines.mapper, 0x210);
+        assert_eq!(ines.submapper, 1);
+        assert_eq!(ines.inesprg, 0x120);
+        assert_eq!(ines.ineschr, 0);
+        assert_eq!(ines.mirroring, 0b1000);
+        assert!(ines.battery);
+        assert_eq!(ines.trainer.as_deref(), Some(&[0xAA; 512][..]));
+        assert_eq!((ines.prg_ram_size, ines.prg_nvram_size), (64 << 7, 0));
+        assert_eq!((ines.chr_ram_size, ines.chr_nvram_size), (64 << 7, 64 << 9));
+        assert_eq!(ines.console, Console::VsSystem);
+        assert_eq!(ines.region, Region::Pal);
+        assert_eq!(ines.expansion_device, 5);
+    }
+
+    #[test]
+    fn test_nes2_exponent_size() {
+        // 2^15 * 1 bytes of PRG
+        let header = *b"NES\x1a\x3C\x00\x00\x08\x00\x0F\x00\x00\x00\x00\x00\x00";
+        let ines = Ines::from_bytes(&file(header, 2, 0)).unwrap();
+        assert_eq!(ines.inesprg, 2);
+        // 2^13 * 3 is not a whole number of 16KiB banks
+        let header = *b"NES\x1a\x35\x00\x00\x08\x00\x0F\x00\x00\x00\x00\x00\x00";
+        assert_eq!(
+            Ines::from_bytes(&file(header, 2, 0)).err(),
+            Some(InesParseError::UnsupportedRomSize)
+        );
+    }
+
+    #[test]
+    fn test_invalid_headers() {
+        let valid = *b"NES\x1a\x01\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00";
+        assert!(Ines::from_bytes(&file(valid, 1, 1)).is_ok());
+        assert_eq!(
+            Ines::from_bytes(&file(valid, 1, 0)).err(),
+            Some(InesParseError::FileInvalidLength)
+        );
+        assert_eq!(
+            Ines::from_bytes(b"NES").err(),
+            Some(InesParseError::InvalidHeader)
+        );
+        let mut header = valid;
+        header[3] = 0;
+        assert_eq!(
+            Ines::from_bytes(&file(header, 1, 1)).err(),
+            Some(InesParseError::InvalidHeader)
+        );
+        let mut header = valid;
+        header[4] = 0;
+        assert_eq!(
+            Ines::from_bytes(&file(header, 0, 1)).err(),
+            Some(InesParseError::NoPrgRom)
+        );
+        // NES 2.0 with PRG-NVRAM but no battery
+        let mut header = valid;
+        header[7] = 0x08;
+        header[10] = 0x70;
+        assert_eq!(
+            Ines::from_bytes(&file(header, 1, 1)).err(),
+            Some(InesParseError::NvramWithoutBattery)
+        );
+    }
+
+    /// Fills PRG and CHR with a pattern so lost or moved bytes show.
+    fn patterned(mut bytes: Vec<u8>, from: usize) -> Vec<u8> {
+        for (i, b) in bytes[from..].iter_mut().enumerate() {
+            *b = (i * 7 % 251) as u8;
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_round_trip_ines1() {
+        let header = *b"NES\x1a\x02\x01\x13\x40\x00\x01\x00\x00\x00\x00\x00\x00";
+        let bytes = patterned(file(header, 2, 1), 16);
+        let ines = Ines::from_bytes(&bytes).unwrap();
+        assert_eq!(ines.to_bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_round_trip_nes2() {
+        let header = *b"NES\x1a\x02\x00\x0E\x19\x12\x00\x07\x97\x03\x00\x01\x05";
+        let mut bytes = patterned(file(header, 2, 0), 16);
+        bytes.splice(16..16, [0xAA; 512]);
+        // a misc ROM after the PRG
+        bytes.extend([1, 2, 3]);
+        let ines = Ines::from_bytes(&bytes).unwrap();
+        assert_eq!(ines.misc_rom, [1, 2, 3]);
+        assert_eq!(ines.to_bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_needs_nes2() {
+        let ines = Ines {
+            inesprg: 1,
+            mapper: 0x123,
+            chr_ram_size: 0x2000,
+            // shorter than the header says
+            banks: vec![0xEA; 100],
+            ..Default::default()
+        };
+        let bytes = ines.to_bytes().unwrap();
+        assert_eq!(bytes.len(), 16 + 0x4000);
+        let parsed = Ines::from_bytes(&bytes).unwrap();
+        assert!(parsed.nes2);
+        assert_eq!(parsed.mapper, 0x123);
+        // no PRG-RAM is not possible in iNES 1.0 either
+        assert_eq!(parsed.prg_ram_size, 0);
+        assert_eq!(parsed.chr_ram_size, 0x2000);
+        assert_eq!(&parsed.banks[..101], &[&[0xEA; 100][..], &[0]].concat()[..]);
+    }
+
+    #[test]
+    fn test_write_to_file() {
+        let header = *b"NES\x1a\x01\x01\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00";
+        let bytes = patterned(file(header, 1, 1), 16);
+        let ines = Ines::from_bytes(&bytes).unwrap();
+        let path = std::env::temp_dir().join(format!("shared-ines-{}.nes", std::process::id()));
+        ines.write_to_file(&path).unwrap();
+        let read = Ines::from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read.banks, ines.banks);
+        assert_eq!(read.mirroring, 1);
+        assert_eq!(
+            read.metadata.unwrap().data_source.as_deref(),
+            Some(path.as_path())
+        );
+    }
+}
@@ -1,20 +1,32 @@
+use crate::ExecutionErrorReason;
 use crate::MemoryTarget;
 use crate::State;
+use crate::opcodes::push_pc;
 use shared::AddressingMode;
+use shared::Opcode;
 
-// TODO implement number of cycles
 /// Increases PC, returns the memory target/adress for opcode
-/// to work on.
-pub fn run(addressing_mode: AddressingMode, state: &mut State) -> MemoryTarget {
+/// to work on. Fails without touching PC for the jam mode.
+/// Does the same dummy reads as the 6502, for opcodes with a page cross
+/// penalty indexed modes that cross a page boundary add an extra cycle.
+pub fn run(
+    addressing_mode: AddressingMode,
+    opcode: &Opcode,
+    state: &mut State,
+) -> Result<MemoryTarget, ExecutionErrorReason> {
     use AddressingMode::*;
     use MemoryTarget::*;
-    match addressing_mode {
+    let page_cross_penalty = opcode.page_cross_penalty();
+    Ok(match addressing_mode {
+        // One byte instructions read the next byte anyway
         IMPL => {
             state.inc_pc();
+            state.read(state.pc, false);
             Impl
         }
         A => {
             state.inc_pc();
+            state.read(state.pc, false);
             Accumulator
         }
         IMM => {
@@ -23,6 +35,17 @@ pub fn run(addressing_mode: AddressingMode, state: &mut State) -> MemoryTarget {
             state.inc_pc();
             a
         }
+        // JSR pushes the return address before it reads the high byte
+        ABS if *opcode == Opcode::JSR => {
+            state.inc_pc();
+            let lo = state.read(state.pc, false);
+            state.inc_pc();
+            state.read(state.sp as u16 | 0x0100, false);
+            push_pc(state);
+            let hi = state.read(state.pc, false);
+            state.inc_pc();
+            Address(lo as u16 + ((hi as u16) << 8))
+        }
         ABS => {
             state.inc_pc();
             let lo = state.read(state.pc, false);
@@ -39,9 +62,9 @@ pub fn run(addressing_mode: AddressingMode, state: &mut State) -> MemoryTarget {
             state.inc_pc();
             // addr + X with carry-over
             let addr = lo as u16 + ((hi as u16) << 8);
-            // TODO implement page-boundary extra cycle when low byte carries over
-            let (addr, _) = addr.overflowing_add(state.x as u16);
-            Address(addr)
+            let (indexed, _) = addr.overflowing_add(state.x as u16);
+            page_cross(state, page_cross_penalty, addr, indexed);
+            Address(indexed)
         }
         ABS_Y => {
             state.inc_pc();
@@ -51,9 +74,9 @@ pub fn run(addressing_mode: AddressingMode, state: &mut State) -> MemoryTarget {
             state.inc_pc();
             // addr + X with carry-over
             let addr = lo as u16 + ((hi as u16) << 8);
-            // TODO implement page-boundary extra cycle when low byte carries over
-            let (addr, _) = addr.overflowing_add(state.y as u16);
-            Address(addr)
+            let (indexed, _) = addr.overflowing_add(state.y as u16);
+            page_cross(state, page_cross_penalty, addr, indexed);
+            Address(indexed)
         }
         REL => {
             state.inc_pc();
@@ -71,13 +94,17 @@ pub fn run(addressing_mode: AddressingMode, state: &mut State) -> MemoryTarget {
             state.inc_pc();
             let lo = state.read(state.pc, false);
             state.inc_pc();
-            Address(lo as u16 + state.x as u16)
+            state.read(lo as u16, false);
+            // Wraps around within the zero page
+            Address(lo.wrapping_add(state.x) as u16)
         }
         ZPG_Y => {
             state.inc_pc();
             let lo = state.read(state.pc, false);
             state.inc_pc();
-            Address(lo as u16 + state.y as u16)
+            state.read(lo as u16, false);
+            // Wraps around within the zero page
+            Address(lo.wrapping_add(state.y) as u16)
         }
         IND => {
             state.inc_pc();
@@ -86,30 +113,50 @@ pub fn run(addressing_mode: AddressingMode, state: &mut State) -> MemoryTarget {
             let hi = state.read(state.pc, false);
             state.inc_pc();
             let pointer = lo as u16 + ((hi as u16) << 8);
+            // The high byte is read without carrying into the page, JMP ($10FF)
+            // reads $10FF and $1000
+            let pointer_hi = (pointer & 0xFF00) | (pointer.wrapping_add(1) & 0x00FF);
             Address(
-                state.read(pointer, false) as u16 + ((state.read(pointer + 1, false) as u16) << 8),
+                state.read(pointer, false) as u16 + ((state.read(pointer_hi, false) as u16) << 8),
             )
         }
         X_IND => {
             state.inc_pc();
             let lo = state.read(state.pc, false);
             state.inc_pc();
-            let pointer = (lo as u16) + (state.x as u16);
+            state.read(lo as u16, false);
+            // The pointer stays within the zero page
+            let pointer = lo.wrapping_add(state.x);
             Address(
-                state.read(pointer, false) as u16 + ((state.read(pointer + 1, false) as u16) << 8),
+                state.read(pointer as u16, false) as u16
+                    + ((state.read(pointer.wrapping_add(1) as u16, false) as u16) << 8),
             )
         }
         IND_Y => {
             state.inc_pc();
             let lo = state.read(state.pc, false);
             state.inc_pc();
-            let pointer = lo as u16;
-            Address(
-                state.read(pointer, false) as u16
-                    + ((state.read(pointer + 1, false) as u16) << 8)
-                    + state.y as u16,
-            )
+            // The pointer stays within the zero page
+            let addr = state.read(lo as u16, false) as u16
+                + ((state.read(lo.wrapping_add(1) as u16, false) as u16) << 8);
+            let (indexed, _) = addr.overflowing_add(state.y as u16);
+            page_cross(state, page_cross_penalty, addr, indexed);
+            Address(indexed)
         }
-        J => unimplemented!("the jam addressing mode (illegal!)"),
+        J => return Err(ExecutionErrorReason::Jam),
+    })
+}
+
+/// Indexing first reads from the page of `base`, before the carry is added to
+/// the high byte. Loads skip that read if there is no carry, then it already
+/// was the right address, at the cost of the extra cycle when there is one.
+/// Stores and read-modify-writes always do it.
+fn page_cross(state: &mut State, page_cross_penalty: bool, base: u16, indexed: u16) {
+    let crossed = (base & 0xFF00) != (indexed & 0xFF00);
+    if page_cross_penalty && crossed {
+        state.cycles += 1;
+    }
+    if crossed || !page_cross_penalty {
+        state.read((base & 0xFF00) | (indexed & 0x00FF), false);
     }
 }
@@ -0,0 +1,741 @@
+//! The APU, https://www.nesdev.org/wiki/APU
+//! Clocked once per CPU cycle, the mixed output is averaged down to
+//! `Apu::sample_rate` and buffered until taken with `State::take_samples`.
+use crate::State;
+use crate::interrupts::IrqSource;
+
+/// NTSC CPU clock in Hz.
+pub const CPU_FREQUENCY: f64 = 1_789_773.0;
+/// Sample rate used unless configured otherwise.
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+const TRIANGLE_SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
+
+/// Noise timer periods in CPU cycles.
+const NOISE_PERIODS: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+
+/// DMC timer periods in CPU cycles.
+const DMC_PERIODS: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+/// Frame counter steps in CPU cycles, quarter frames at every step and
+/// half frames at the second and last one.
+const FOUR_STEP: [u64; 4] = [7457, 14913, 22371, 29829];
+const FIVE_STEP: [u64; 5] = [7457, 14913, 22371, 29829, 37281];
+
+/// Volume envelope shared by the pulse and noise channels.
+#[derive(Default)]
+struct Envelope {
+    start: bool,
+    /// Also halts the length counter.
+    looping: bool,
+    constant: bool,
+    /// Constant volume, or the period of the decay.
+    volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    fn write(&mut self, value: u8) {
+        self.looping = value & 0x20 != 0;
+        self.constant = value & 0x10 != 0;
+        self.volume = value & 0x0F;
+    }
+
+    fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.constant {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
+
+#[derive(Default)]
+struct Pulse {
+    /// Pulse 1 negates with ones' complement.
+    ones_complement: bool,
+    enabled: bool,
+    duty: u8,
+    step: u8,
+    period: u16,
+    timer: u16,
+    length: u8,
+    envelope: Envelope,
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+}
+
+impl Pulse {
+    fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.duty = value >> 6;
+                self.envelope.write(value);
+            }
+            1 => {
+                self.sweep_enabled = value & 0x80 != 0;
+                self.sweep_period = (value >> 4) & 0b111;
+                self.sweep_negate = value & 0x08 != 0;
+                self.sweep_shift = value & 0b111;
+                self.sweep_reload = true;
+            }
+            2 => self.period = (self.period & 0x0700) | value as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((value & 0b111) as u16) << 8;
+                if self.enabled {
+                    self.length = LENGTH_TABLE[(value >> 3) as usize];
+                }
+                self.step = 0;
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    /// Clocked every other CPU cycle.
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn sweep_target(&self) -> u16 {
+        let change = self.period >> self.sweep_shift;
+        if self.sweep_negate {
+            let change = change + self.ones_complement as u16;
+            self.period.saturating_sub(change)
+        } else {
+            self.period + change
+        }
+    }
+
+    fn muted(&self) -> bool {
+        self.period < 8 || self.sweep_target() > 0x07FF
+    }
+
+    fn clock_half_frame(&mut self) {
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.muted() {
+            self.period = self.sweep_target();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+        if !self.envelope.looping && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.length == 0
+            || self.muted()
+            || DUTY_TABLE[self.duty as usize][self.step as usize] == 0
+        {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+#[derive(Default)]
+struct Triangle {
+    enabled: bool,
+    /// Also halts the length counter.
+    control: bool,
+    linear_reload_value: u8,
+    linear_reload: bool,
+    linear: u8,
+    length: u8,
+    period: u16,
+    timer: u16,
+    step: u8,
+}
+
+impl Triangle {
+    fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.control = value & 0x80 != 0;
+                self.linear_reload_value = value & 0x7F;
+            }
+            2 => self.period = (self.period & 0x0700) | value as u16,
+            3 => {
+                self.period = (self.period & 0x00FF) | ((value & 0b111) as u16) << 8;
+                if self.enabled {
+                    self.length = LENGTH_TABLE[(value >> 3) as usize];
+                }
+                self.linear_reload = true;
+            }
+            _ => {}
+        }
+    }
+
+    /// Clocked every CPU cycle.
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.linear > 0 && self.length > 0 {
+                self.step = (self.step + 1) % 32;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        if self.linear_reload {
+            self.linear = self.linear_reload_value;
+        } else if self.linear > 0 {
+            self.linear -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    fn clock_half_frame(&mut self) {
+        if !self.control && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        // Ultrasonic periods are silenced instead of producing a DC offset
+        if self.period < 2 {
+            7
+        } else {
+            TRIANGLE_SEQUENCE[self.step as usize]
+        }
+    }
+}
+
+struct Noise {
+    enabled: bool,
+    envelope: Envelope,
+    /// Short mode, feedback from bit 6 instead of bit 1.
+    mode: bool,
+    period: u16,
+    timer: u16,
+    shift: u16,
+    length: u8,
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            envelope: Envelope::default(),
+            mode: false,
+            period: NOISE_PERIODS[0],
+            timer: 0,
+            shift: 1,
+            length: 0,
+        }
+    }
+}
+
+impl Noise {
+    fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => self.envelope.write(value),
+            2 => {
+                self.mode = value & 0x80 != 0;
+                self.period = NOISE_PERIODS[(value & 0x0F) as usize];
+            }
+            3 => {
+                if self.enabled {
+                    self.length = LENGTH_TABLE[(value >> 3) as usize];
+                }
+                self.envelope.start = true;
+            }
+            _ => {}
+        }
+    }
+
+    /// Clocked every CPU cycle.
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period - 1;
+            let other = if self.mode { 6 } else { 1 };
+            let feedback = (self.shift ^ (self.shift >> other)) & 1;
+            self.shift = (self.shift >> 1) | feedback << 14;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_half_frame(&mut self) {
+        if !self.envelope.looping && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.length == 0 || self.shift & 1 != 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+/// Delta modulation channel, plays 1-bit deltas fetched from the CPU bus.
+struct Dmc {
+    irq_enabled: bool,
+    irq: bool,
+    looping: bool,
+    period: u16,
+    timer: u16,
+    level: u8,
+    sample_address: u16,
+    sample_length: u16,
+    address: u16,
+    remaining: u16,
+    buffer: Option<u8>,
+    shift: u8,
+    bits: u8,
+    silence: bool,
+}
+
+impl Default for Dmc {
+    fn default() -> Self {
+        Self {
+            irq_enabled: false,
+            irq: false,
+            looping: false,
+            period: DMC_PERIODS[0],
+            timer: 0,
+            level: 0,
+            sample_address: 0xC000,
+            sample_length: 1,
+            address: 0xC000,
+            remaining: 0,
+            buffer: None,
+            shift: 0,
+            bits: 8,
+            silence: true,
+        }
+    }
+}
+
+impl Dmc {
+    fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.irq_enabled = value & 0x80 != 0;
+                self.looping = value & 0x40 != 0;
+                self.period = DMC_PERIODS[(value & 0x0F) as usize];
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+            }
+            1 => self.level = value & 0x7F,
+            2 => self.sample_address = 0xC000 | (value as u16) << 6,
+            _ => self.sample_length = (value as u16) << 4 | 1,
+        }
+    }
+
+    fn restart(&mut self) {
+        self.address = self.sample_address;
+        self.remaining = self.sample_length;
+    }
+
+    /// The address of the next sample byte, if the buffer needs one.
+    fn fetch_address(&self) -> Option<u16> {
+        (self.buffer.is_none() && self.remaining > 0).then_some(self.address)
+    }
+
+    fn load(&mut self, value: u8) {
+        self.buffer = Some(value);
+        self.address = self.address.checked_add(1).unwrap_or(0x8000);
+        self.remaining -= 1;
+        if self.remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    /// Clocked every CPU cycle.
+    fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period - 1;
+        if !self.silence {
+            if self.shift & 1 != 0 {
+                if self.level <= 125 {
+                    self.level += 2;
+                }
+            } else if self.level >= 2 {
+                self.level -= 2;
+            }
+        }
+        self.shift >>= 1;
+        self.bits -= 1;
+        if self.bits == 0 {
+            self.bits = 8;
+            match self.buffer.take() {
+                Some(value) => {
+                    self.silence = false;
+                    self.shift = value;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+}
+
+/// Names of the channels, in the order of `Apu::take_channel_samples`.
+pub const CHANNELS: [&str; 5] = ["pulse1", "pulse2", "triangle", "noise", "dmc"];
+
+/// A stream of averaged and filtered output samples.
+#[derive(Default)]
+struct Output {
+    /// Sum of the outputs since the last sample, for averaging.
+    accumulated: f32,
+    /// State of the DC blocking filter.
+    filter_input: f32,
+    filter_output: f32,
+    samples: Vec<f32>,
+}
+
+impl Output {
+    /// Averages what was accumulated over `count` cycles into one sample.
+    fn finish(&mut self, count: u32, sample_rate: u32) {
+        let input = self.accumulated / count as f32;
+        self.accumulated = 0.0;
+        // First order high-pass at 90Hz like the hardware, removes the DC offset
+        let rc = 1.0 / (2.0 * std::f32::consts::PI * 90.0);
+        let alpha = rc / (rc + 1.0 / sample_rate as f32);
+        self.filter_output = alpha * (self.filter_output + input - self.filter_input);
+        self.filter_input = input;
+        // Samples nobody takes are dropped after a second
+        if self.samples.len() >= sample_rate as usize {
+            self.samples.drain(..sample_rate as usize / 2);
+        }
+        self.samples.push(self.filter_output);
+    }
+}
+
+/// All APU state.
+pub struct Apu {
+    /// Output samples per second, 0 disables sample output.
+    pub sample_rate: u32,
+    /// Also produce a separate sample stream for every channel.
+    pub record_channels: bool,
+    output: Output,
+    channels: [Output; 5],
+    pulse1: Pulse,
+    pulse2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+    five_step: bool,
+    irq_inhibit: bool,
+    frame_irq: bool,
+    /// CPU cycles since the frame counter was reset.
+    frame_cycle: u64,
+    /// Counts every CPU cycle, the pulse timers run on the even ones.
+    cycle: u64,
+    /// Cycles accumulated since the last sample.
+    accumulated_count: u32,
+    /// Fraction of a sample that has passed, in units of CPU cycles.
+    sample_clock: f64,
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Self {
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            record_channels: false,
+            output: Output::default(),
+            channels: Default::default(),
+            pulse1: Pulse {
+                ones_complement: true,
+                ..Default::default()
+            },
+            pulse2: Pulse::default(),
+            triangle: Triangle::default(),
+            noise: Noise::default(),
+            dmc: Dmc::default(),
+            five_step: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            frame_cycle: 0,
+            cycle: 0,
+            accumulated_count: 0,
+            sample_clock: 0.0,
+        }
+    }
+
+    /// Takes the samples produced since the last call.
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.output.samples)
+    }
+
+    /// Takes the samples of every channel on its own, see `record_channels`.
+    pub fn take_channel_samples(&mut self) -> [Vec<f32>; 5] {
+        self.channels
+            .each_mut()
+            .map(|channel| std::mem::take(&mut channel.samples))
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.pulse1.envelope.clock();
+        self.pulse2.envelope.clock();
+        self.triangle.clock_quarter_frame();
+        self.noise.envelope.clock();
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.pulse1.clock_half_frame();
+        self.pulse2.clock_half_frame();
+        self.triangle.clock_half_frame();
+        self.noise.clock_half_frame();
+    }
+
+    fn clock_frame_counter(&mut self) {
+        self.frame_cycle += 1;
+        if self.five_step {
+            match self.frame_cycle {
+                c if c == FIVE_STEP[0] || c == FIVE_STEP[2] => self.clock_quarter_frame(),
+                c if c == FIVE_STEP[1] => {
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
+                }
+                c if c == FIVE_STEP[4] => {
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
+                    self.frame_cycle = 0;
+                }
+                _ => {}
+            }
+        } else {
+            match self.frame_cycle {
+                c if c == FOUR_STEP[0] || c == FOUR_STEP[2] => self.clock_quarter_frame(),
+                c if c == FOUR_STEP[1] => {
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
+                }
+                c if c == FOUR_STEP[3] => {
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
+                    if !self.irq_inhibit {
+                        self.frame_irq = true;
+                    }
+                    self.frame_cycle = 0;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The non-linear mix of the channel outputs, 0.0 to 1.0.
+    fn mix(pulse1: u8, pulse2: u8, triangle: u8, noise: u8, dmc: u8) -> f32 {
+        let pulse = (pulse1 + pulse2) as f32;
+        let pulse_out = if pulse == 0.0 {
+            0.0
+        } else {
+            95.88 / (8128.0 / pulse + 100.0)
+        };
+        let tnd = triangle as f32 / 8227.0 + noise as f32 / 12241.0 + dmc as f32 / 22638.0;
+        let tnd_out = if tnd == 0.0 {
+            0.0
+        } else {
+            159.79 / (1.0 / tnd + 100.0)
+        };
+        pulse_out + tnd_out
+    }
+
+    /// Averages the mix over the cycles of one output sample.
+    fn sample(&mut self) {
+        if self.sample_rate == 0 {
+            return;
+        }
+        let outputs = [
+            self.pulse1.output(),
+            self.pulse2.output(),
+            self.triangle.output(),
+            self.noise.output(),
+            self.dmc.level,
+        ];
+        let [pulse1, pulse2, triangle, noise, dmc] = outputs;
+        self.output.accumulated += Self::mix(pulse1, pulse2, triangle, noise, dmc);
+        if self.record_channels {
+            for (i, channel) in self.channels.iter_mut().enumerate() {
+                // Each channel through the mixer as if the others were silent
+                let mut alone = [0; 5];
+                alone[i] = outputs[i];
+                let [pulse1, pulse2, triangle, noise, dmc] = alone;
+                channel.accumulated += Self::mix(pulse1, pulse2, triangle, noise, dmc);
+            }
+        }
+        self.accumulated_count += 1;
+        self.sample_clock += self.sample_rate as f64;
+        if self.sample_clock < CPU_FREQUENCY {
+            return;
+        }
+        self.sample_clock -= CPU_FREQUENCY;
+        self.output.finish(self.accumulated_count, self.sample_rate);
+        if self.record_channels {
+            for channel in &mut self.channels {
+                channel.finish(self.accumulated_count, self.sample_rate);
+            }
+        }
+        self.accumulated_count = 0;
+    }
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Handles a CPU write to $4000-$4013, $4015 and $4017.
+pub fn write_register(state: &mut State, address: u16, value: u8) {
+    let apu = &mut state.apu;
+    let register = address & 0b11;
+    match address {
+        0x4000..=0x4003 => apu.pulse1.write(register, value),
+        0x4004..=0x4007 => apu.pulse2.write(register, value),
+        0x4008..=0x400B => apu.triangle.write(register, value),
+        0x400C..=0x400F => apu.noise.write(register, value),
+        0x4010..=0x4013 => apu.dmc.write(register, value),
+        // Channel enables
+        0x4015 => {
+            apu.pulse1.enabled = value & 0x01 != 0;
+            apu.pulse2.enabled = value & 0x02 != 0;
+            apu.triangle.enabled = value & 0x04 != 0;
+            apu.noise.enabled = value & 0x08 != 0;
+            if !apu.pulse1.enabled {
+                apu.pulse1.length = 0;
+            }
+            if !apu.pulse2.enabled {
+                apu.pulse2.length = 0;
+            }
+            if !apu.triangle.enabled {
+                apu.triangle.length = 0;
+            }
+            if !apu.noise.enabled {
+                apu.noise.length = 0;
+            }
+            if value & 0x10 == 0 {
+                apu.dmc.remaining = 0;
+            } else if apu.dmc.remaining == 0 {
+                apu.dmc.restart();
+            }
+            apu.dmc.irq = false;
+        }
+        // Frame counter
+        0x4017 => {
+            apu.five_step = value & 0x80 != 0;
+            apu.irq_inhibit = value & 0x40 != 0;
+            if apu.irq_inhibit {
+                apu.frame_irq = false;
+            }
+            apu.frame_cycle = 0;
+            if apu.five_step {
+                apu.clock_quarter_frame();
+                apu.clock_half_frame();
+            }
+        }
+        _ => {}
+    }
+    update_irq(state);
+}
+
+/// Reads $4015, which acknowledges the frame interrupt unless `read_only` is set.
+pub fn read_status(state: &mut State, read_only: bool) -> u8 {
+    let apu = &mut state.apu;
+    let status = (apu.pulse1.length > 0) as u8
+        | ((apu.pulse2.length > 0) as u8) << 1
+        | ((apu.triangle.length > 0) as u8) << 2
+        | ((apu.noise.length > 0) as u8) << 3
+        | ((apu.dmc.remaining > 0) as u8) << 4
+        | (apu.frame_irq as u8) << 6
+        | (apu.dmc.irq as u8) << 7;
+    if !read_only {
+        apu.frame_irq = false;
+        update_irq(state);
+    }
+    status
+}
+
+fn update_irq(state: &mut State) {
+    let (frame_irq, dmc_irq) = (state.apu.frame_irq, state.apu.dmc.irq);
+    state.set_irq(IrqSource::FrameCounter, frame_irq);
+    state.set_irq(IrqSource::Dmc, dmc_irq);
+}
+
+/// Runs the APU for the given number of CPU cycles.
+pub fn step(state: &mut State, cpu_cycles: u64) {
+    for _ in 0..cpu_cycles {
+        let apu = &mut state.apu;
+        apu.cycle += 1;
+        apu.clock_frame_counter();
+        if apu.cycle.is_multiple_of(2) {
+            apu.pulse1.clock_timer();
+            apu.pulse2.clock_timer();
+        }
+        apu.triangle.clock_timer();
+        apu.noise.clock_timer();
+        apu.dmc.clock_timer();
+        if let Some(address) = apu.dmc.fetch_address() {
+            let value = state.read(address, false);
+            state.apu.dmc.load(value);
+        }
+        state.apu.sample();
+    }
+    update_irq(state);
+}
@@ -0,0 +1,70 @@
+//! Standard controllers on $4016/$4017, https://www.nesdev.org/wiki/Standard_controller
+
+/// Button bits, in the order the controller reports them.
+pub mod buttons {
+    pub const A: u8 = 1 << 0;
+    pub const B: u8 = 1 << 1;
+    pub const SELECT: u8 = 1 << 2;
+    pub const START: u8 = 1 << 3;
+    pub const UP: u8 = 1 << 4;
+    pub const DOWN: u8 = 1 << 5;
+    pub const LEFT: u8 = 1 << 6;
+    pub const RIGHT: u8 = 1 << 7;
+}
+
+/// The two controller ports, read through $4016 and $4017.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Port {
+    One,
+    Two,
+}
+
+impl Port {
+    /// Index into `State::controllers`.
+    pub fn index(self) -> usize {
+        match self {
+            Port::One => 0,
+            Port::Two => 1,
+        }
+    }
+}
+
+/// A joypad, a shift register that is reloaded from the buttons while strobe is set.
+#[derive(Default)]
+pub struct Controller {
+    /// Currently held buttons, see `buttons`.
+    pub buttons: u8,
+    shift: u8,
+    /// Number of bits shifted out since the last reload.
+    shifted: u8,
+    strobe: bool,
+}
+
+impl Controller {
+    /// The lowest bit of a write to $4016.
+    pub fn write_strobe(&mut self, strobe: bool) {
+        self.strobe = strobe;
+        if strobe {
+            self.reload();
+        }
+    }
+
+    /// Reads the next button, without shifting if `read_only` is set.
+    /// After all eight the official controllers return 1.
+    pub fn read(&mut self, read_only: bool) -> u8 {
+        if self.strobe {
+            self.reload();
+        }
+        let bit = if self.shifted < 8 { self.shift & 1 } else { 1 };
+        if !read_only && !self.strobe && self.shifted < 8 {
+            self.shift >>= 1;
+            self.shifted += 1;
+        }
+        bit
+    }
+
+    fn reload(&mut self) {
+        self.shift = self.buttons;
+        self.shifted = 0;
+    }
+}
@@ -0,0 +1,65 @@
+//! Interrupt delivery, https://www.nesdev.org/wiki/CPU_interrupts
+use crate::State;
+use crate::opcodes::push;
+use crate::opcodes::push_pc;
+use shared::flags;
+use shared::vectors;
+
+/// Cycles spent pushing the state and jumping through a vector.
+pub const INTERRUPT_CYCLES: u64 = 7;
+
+/// Devices that can pull the IRQ line low, the line stays asserted
+/// as long as any of them hold it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqSource {
+    /// The APU frame counter.
+    FrameCounter,
+    /// The APU DMC channel.
+    Dmc,
+    /// Cartridge hardware, for example a scanline counter.
+    Mapper,
+    /// Anything else, e.g. test devices.
+    External,
+}
+
+impl IrqSource {
+    /// The bit used for this source in `State::irq_lines`.
+    pub fn bit(self) -> u8 {
+        match self {
+            IrqSource::FrameCounter => 1 << 0,
+            IrqSource::Dmc => 1 << 1,
+            IrqSource::Mapper => 1 << 2,
+            IrqSource::External => 1 << 3,
+        }
+    }
+}
+
+/// Services a pending NMI or (unmasked) IRQ, to be called between instructions.
+/// An IRQ is masked by I as it was before a CLI, SEI or PLP that just ran, so
+/// one more instruction runs after CLI. RTI changes I in time for the poll.
+/// Returns true if an interrupt was taken.
+pub fn poll(state: &mut State) -> bool {
+    let masked = state.stale_i.take().unwrap_or(state.get_flag(flags::I));
+    if state.nmi_pending {
+        state.nmi_pending = false;
+        interrupt(state, vectors::NMI, false);
+        state.cycles += INTERRUPT_CYCLES;
+        true
+    } else if state.irq_lines != 0 && !masked {
+        interrupt(state, vectors::IRQ, false);
+        state.cycles += INTERRUPT_CYCLES;
+        true
+    } else {
+        false
+    }
+}
+
+/// Pushes PC and the status register and jumps through `vector`.
+/// The B flag is only set in the pushed status when the interrupt comes from BRK.
+pub fn interrupt(state: &mut State, vector: u16, brk: bool) {
+    push_pc(state);
+    let sr = (state.sr | flags::U) & !flags::B;
+    push(state, if brk { sr | flags::B } else { sr });
+    state.set_flag(flags::I, true);
+    state.pc = state.read_u16(vector);
+}
@@ -1,23 +1,37 @@
-#![feature(let_chains)]
 #![forbid(clippy::undocumented_unsafe_blocks)]
 pub mod addressing_modes;
+pub mod apu;
+pub mod controller;
+pub mod interrupts;
+pub mod mappers;
 pub mod memory;
 pub mod opcodes;
+pub mod ppu;
+pub mod trace;
+pub mod wav;
 
-use std::error::Error;
+use std::fs;
+use std::io;
+use std::io::Write;
 use std::ops::RangeInclusive;
 use std::path::Path;
 use std::path::PathBuf;
-use std::usize;
 
+use apu::Apu;
 use asmnes::assemble;
+use controller::Controller;
+use controller::Port;
+use interrupts::IrqSource;
 use log::debug;
-use shared::AddressingMode;
-use shared::BANK_SIZE;
+use mappers::CHR_WINDOW;
+use mappers::Mapper;
+use mappers::PRG_WINDOW;
+use ppu::Mirroring;
 use shared::CODEPOINTS;
 use shared::Codepoint;
 use shared::Ines;
 use shared::Opcode;
+use shared::flags;
 
 /// The state of the NES, registers, all devices mapped to memory-regions
 pub struct State {
@@ -43,12 +57,47 @@ pub struct State {
     pub sp: u8,
     /// Number of cycles that have passed.
     pub cycles: u64,
+    /// The chip-dependent constant used by the unstable ANE and LXA opcodes.
+    pub magic: u8,
+    /// An NMI has been signaled and will be serviced before the next instruction.
+    pub nmi_pending: bool,
+    /// One bit per `IrqSource` currently asserting the IRQ line.
+    pub irq_lines: u8,
+    /// The I flag from before the last instruction if that was CLI, SEI or PLP.
+    /// They change I after the interrupt poll, so the next poll still uses it.
+    stale_i: Option<bool>,
+    /// If set, a nestest-style trace line is written here before every instruction.
+    pub trace: Option<Box<dyn Write>>,
     /// The static cartridge information.
     pub ines: Ines,
-    /// The dynamic memory mappings.
-    pub memory: Vec<MemoryMap>,
+    /// The dynamic memory mappings, `page_table` indexes into them so they
+    /// only change through `map_device`.
+    memory: Vec<MemoryMap>,
+    /// Which memory map answers for each page, derived from `memory`.
+    page_table: PageTable,
     /// All PPU state
     pub ppu_state: PpuState,
+    /// The joypads in port 1 and 2.
+    pub controllers: [Controller; 2],
+    /// All APU state.
+    pub apu: Apu,
+    /// The cartridge's banking hardware.
+    pub mapper: Box<dyn Mapper>,
+    /// What the mapper currently shows, derived from `mapper`.
+    banks: Banks,
+    /// CHR-RAM for cartridges without CHR-ROM, empty otherwise.
+    pub chr_ram: Vec<u8>,
+    /// PRG-RAM at $6000-$7FFF as large as the header says, mirrored when
+    /// smaller than 8KiB. See `export_save` for battery saves.
+    pub prg_ram: Vec<u8>,
+}
+
+/// Byte offsets of the visible PRG and CHR windows, into `Ines::banks`
+/// for ROM and into `State::chr_ram` for CHR-RAM.
+#[derive(Default)]
+struct Banks {
+    prg: [usize; 4],
+    chr: [usize; 8],
 }
 
 /// A device with can be mapped to memory regions on the cpu-bus or the ppu-bus
@@ -64,11 +113,96 @@ pub struct MemoryRegion {
 }
 pub enum Device {
     Ram(Vec<u8>),
-    /// Bank index
-    Rom(usize),
+    /// PRG-ROM at $8000-$FFFF, banked by the mapper which also gets the writes.
+    Prg,
+    /// The pattern tables, CHR-ROM or CHR-RAM banked by the mapper.
+    Chr,
+    /// `State::prg_ram`, the mapper can disable or write protect it.
+    PrgRam,
     Palette(Box<[u8; 32]>),
+    /// $2000-$3EFF on the PPU bus, `PpuState::vram` through `PpuState::mirroring`.
+    Nametables,
     PpuRegisters,
+    /// The APU and I/O registers at $4000-$401F.
+    IoRegisters,
+    /// Anything implementing `BusDevice`, e.g. a debug port in a test.
+    Custom(Box<dyn BusDevice>),
+}
+
+/// Memory-mapped hardware that can be attached with `State::map_device`.
+/// Addresses are offsets from the start of the region that was accessed.
+pub trait BusDevice {
+    fn read(&mut self, offset: u16) -> u8;
+    fn write(&mut self, offset: u16, value: u8);
+    /// Like `read`, without side effects, used by debuggers and dumps.
+    fn peek(&self, offset: u16) -> u8;
+    /// Called after every instruction with the number of CPU cycles it took.
+    fn tick(&mut self, _cycles: u64) {}
+}
+/// Size of the pages the bus decoder looks devices up by.
+const PAGE_SIZE: usize = 0x100;
+
+/// Which device answers for a page of an address space.
+#[derive(Clone, Copy)]
+enum Page {
+    Unmapped,
+    /// Index into `State::memory`, and the start of the region covering the page.
+    Mapped {
+        map: usize,
+        start: u16,
+    },
+    /// The page is shared between regions, these are looked up linearly.
+    Split,
 }
+
+/// Precomputed lookup of devices per page, for both address spaces.
+struct PageTable {
+    cpu: Box<[Page; 0x10000 / PAGE_SIZE]>,
+    ppu: Box<[Page; 0x10000 / PAGE_SIZE]>,
+}
+
+impl PageTable {
+    fn new(memory: &[MemoryMap]) -> Self {
+        Self {
+            cpu: Box::new(std::array::from_fn(|page| {
+                Self::decode_page(memory, AddressSpace::Cpu, page)
+            })),
+            ppu: Box::new(std::array::from_fn(|page| {
+                Self::decode_page(memory, AddressSpace::Ppu, page)
+            })),
+        }
+    }
+
+    /// The first region touching the page wins, like the linear lookup.
+    fn decode_page(memory: &[MemoryMap], address_space: AddressSpace, page: usize) -> Page {
+        let first = (page * PAGE_SIZE) as u16;
+        let last = first + (PAGE_SIZE - 1) as u16;
+        memory
+            .iter()
+            .enumerate()
+            .find_map(|(map, m)| {
+                m.memory_regions
+                    .iter()
+                    .find(|mr| {
+                        mr.address_space == address_space
+                            && *mr.range.start() <= last
+                            && *mr.range.end() >= first
+                    })
+                    .map(|mr| {
+                        if mr.range.contains(&first) && mr.range.contains(&last) {
+                            Page::Mapped {
+                                map,
+                                start: *mr.range.start(),
+                            }
+                        } else {
+                            Page::Split
+                        }
+                    })
+            })
+            .unwrap_or(Page::Unmapped)
+    }
+}
+
 /// There are separate address spaces, the CPU + some PPU ones
 /// https://www.nesdev.org/wiki/PPU
 #[derive(PartialEq)]
@@ -78,26 +212,73 @@ pub enum AddressSpace {
 }
 
 pub struct PpuState {
-    /// This gets set when writing to PPUADDR twice, first the high byte then the low byte.
-    pub tmp_addr: Option<u16>,
+    /// Current VRAM address, also used for scrolling while rendering.
+    /// https://www.nesdev.org/wiki/PPU_scrolling
+    pub v: u16,
+    /// Temporary VRAM address, the top left corner of the screen.
+    pub t: u16,
+    /// Fine X scroll, 3 bits.
+    pub x: u8,
+    /// Write toggle shared by PPUSCROLL and PPUADDR, set after the first write.
+    pub w: bool,
     /// This is the buffered value that is saved for the next read when reading from PPUDATA.
     pub tmp_val: u8,
-    /// Unreliable vblank detection flag.
+    /// PPUCTRL, see `ppu::ctrl`.
+    pub ctrl: u8,
+    /// PPUMASK, see `ppu::mask`.
+    pub mask: u8,
+    /// OAMADDR, where OAMDATA reads and writes.
+    pub oam_addr: u8,
+    /// Sprite attribute memory, 4 bytes per sprite.
+    pub oam: Box<[u8; 256]>,
+    /// Set at the start of vblank, cleared on the pre-render line or by reading PPUSTATUS.
     pub vblank: bool,
-    /// Crazy hit detection.
+    /// An opaque pixel of sprite 0 overlapped an opaque background pixel.
     pub sprite_0_hit: bool,
-    /// Buggy detection to check if there are more than 8 sprites on a scanline.
+    /// More than 8 sprites on a scanline.
     pub sprite_overflow: bool,
+    /// Current scanline, 0-239 are visible, 261 is the pre-render line.
+    pub scanline: u16,
+    /// Current dot in the scanline, 0-340.
+    pub dot: u16,
+    /// Number of frames completed, increases when vblank starts.
+    pub frame: u64,
+    pub odd_frame: bool,
+    /// Dots since power-on.
+    pub dots: u64,
+    /// Indices into `ppu::PALETTE`, `ppu::WIDTH` pixels per row.
+    pub framebuffer: Box<[u8; ppu::WIDTH * ppu::HEIGHT]>,
+    /// Nametable RAM, the 2KiB CIRAM followed by the 2KiB a four-screen
+    /// cartridge adds.
+    pub vram: Box<[u8; 0x1000]>,
+    /// Selected from the header, mappers may change it at any time.
+    pub mirroring: Mirroring,
 }
 
 impl PpuState {
     fn new() -> Self {
         Self {
-            tmp_addr: None,
+            v: 0,
+            t: 0,
+            x: 0,
+            w: false,
             tmp_val: 0,
+            ctrl: 0,
+            mask: 0,
+            oam_addr: 0,
+            // Y coordinates below the screen
+            oam: Box::new([0xFF; 256]),
             vblank: false,
             sprite_0_hit: false,
             sprite_overflow: false,
+            scanline: 0,
+            dot: 0,
+            frame: 0,
+            odd_frame: false,
+            dots: 0,
+            framebuffer: Box::new([0; ppu::WIDTH * ppu::HEIGHT]),
+            vram: Box::new([0; 0x1000]),
+            mirroring: Mirroring::Horizontal,
         }
     }
 }
@@ -107,6 +288,8 @@ pub enum FileError {
     AsmnesError(asmnes::AsmnesError),
     InesError(shared::InesError),
     InvalidFileType,
+    /// The iNES mapper number is not implemented.
+    UnsupportedMapper(u16),
 }
 
 use std::fmt;
@@ -118,10 +301,50 @@ impl fmt::Display for FileError {
             FileError::AsmnesError(e) => write!(f, "{e}"),
             FileError::InesError(e) => write!(f, "{e}"),
             FileError::InvalidFileType => write!(f, "supports files of type .nes or .asm"),
+            FileError::UnsupportedMapper(mapper) => write!(f, "mapper {mapper} is not supported"),
         }
     }
 }
 
+/// Why the CPU could not execute an instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExecutionErrorReason {
+    /// A JAM opcode, halts the CPU.
+    Jam,
+    /// The opcode is not implemented for its addressing mode.
+    Unimplemented,
+}
+
+/// Error when running an instruction, PC points at the instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecutionError {
+    pub pc: u16,
+    /// The raw opcode byte.
+    pub opcode: u8,
+    pub reason: ExecutionErrorReason,
+}
+
+impl std::error::Error for ExecutionError {}
+
+impl fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Codepoint {
+            opcode,
+            addressing_mode,
+            ..
+        } = &CODEPOINTS[self.opcode as usize];
+        let reason = match self.reason {
+            ExecutionErrorReason::Jam => "CPU jammed",
+            ExecutionErrorReason::Unimplemented => "not implemented",
+        };
+        write!(
+            f,
+            "{reason}: {opcode} {addressing_mode:?} (${:02X}) at ${:04X}",
+            self.opcode, self.pc
+        )
+    }
+}
+
 pub fn load_from_file<T: AsRef<Path>>(path: T) -> Result<Ines, FileError> {
     if let Some(os_str) = path.as_ref().extension() {
         match os_str.to_str() {
@@ -135,90 +358,45 @@ pub fn load_from_file<T: AsRef<Path>>(path: T) -> Result<Ines, FileError> {
 }
 
 impl State {
-    pub fn new(ines: Ines) -> Self {
-        let pc = 0;
-        let x = 0;
-        let a = 0;
-        let y = 0;
-        let sr = 0;
-        let sp = 0xFF;
-        let cycles = 0;
-        let mut memory: Vec<MemoryMap> = Vec::new();
-        memory.push(MemoryMap {
+    /// Fails if the cartridge needs a mapper that is not implemented.
+    pub fn new(ines: Ines) -> Result<Self, FileError> {
+        let mapper = mappers::from_ines(&ines)?;
+        let mut memory: Vec<MemoryMap> = vec![MemoryMap {
             memory_regions: vec![MemoryRegion {
                 address_space: AddressSpace::Cpu,
                 range: 0x0000..=0x07FF,
             }],
             device: Device::Ram(vec![0; 0x0800]),
+        }];
+        memory.push(MemoryMap {
+            memory_regions: vec![MemoryRegion {
+                address_space: AddressSpace::Cpu,
+                range: 0x6000..=0x7FFF,
+            }],
+            device: Device::PrgRam,
+        });
+        // The mapper decides what appears in these
+        memory.push(MemoryMap {
+            memory_regions: vec![MemoryRegion {
+                address_space: AddressSpace::Cpu,
+                range: 0x8000..=0xFFFF,
+            }],
+            device: Device::Prg,
         });
-        if ines.inesprg == 1 {
-            memory.push(MemoryMap {
-                memory_regions: vec![
-                    MemoryRegion {
-                        address_space: AddressSpace::Cpu,
-                        range: 0x8000..=0x9FFF,
-                    },
-                    // Mirrored region
-                    MemoryRegion {
-                        address_space: AddressSpace::Cpu,
-                        range: 0xC000..=0xDFFF,
-                    },
-                ],
-                device: Device::Rom(0),
-            });
-            memory.push(MemoryMap {
-                memory_regions: vec![
-                    MemoryRegion {
-                        address_space: AddressSpace::Cpu,
-                        range: 0xA000..=0xBFFF,
-                    },
-                    // Mirrored region
-                    MemoryRegion {
-                        address_space: AddressSpace::Cpu,
-                        range: 0xE000..=0xFFFF,
-                    },
-                ],
-                device: Device::Rom(1),
-            });
-        } else {
-            // The other 16KiB
-            memory.push(MemoryMap {
-                memory_regions: vec![MemoryRegion {
-                    address_space: AddressSpace::Cpu,
-                    range: 0xC000..=0xDFFF,
-                }],
-                device: Device::Rom(3),
-            });
-            memory.push(MemoryMap {
-                memory_regions: vec![MemoryRegion {
-                    address_space: AddressSpace::Cpu,
-                    range: 0xE000..=0xFFFF,
-                }],
-                device: Device::Rom(4),
-            });
-        }
-
-        // 8KiB pattern memory
         memory.push(MemoryMap {
             memory_regions: vec![MemoryRegion {
                 address_space: AddressSpace::Ppu,
-                // TODO when working with the ppu
                 range: 0x0000..=0x1FFF,
             }],
-            device: Device::Rom(if ines.inesprg == 1 { 3 } else { 5 }),
+            device: Device::Chr,
         });
 
-        // VRAM; Nametables
-        let nametables_range: RangeInclusive<u16> = 0x2000..=0x3EFF;
+        // VRAM; Nametables, $3000-$3EFF mirrors $2000-$2EFF
         memory.push(MemoryMap {
-            device: Device::Ram(vec![
-                0;
-                (nametables_range.end() + 1 - nametables_range.start())
-                    as usize
-            ]),
+            device: Device::Nametables,
             memory_regions: vec![MemoryRegion {
                 address_space: AddressSpace::Ppu,
-                range: nametables_range,
+                range: 0x2000..=0x3EFF,
             }],
         });
 
@@ -242,9 +420,111 @@ impl State {
             }],
         });
 
-        let ppu_state = PpuState::new();
+        // APU and I/O registers
+        memory.push(MemoryMap {
+            device: Device::IoRegisters,
+            memory_regions: vec![MemoryRegion {
+                address_space: AddressSpace::Cpu,
+                range: 0x4000..=0x401F,
+            }],
+        });
+
+        let mirroring = Mirroring::from_ines(ines.mirroring);
+        let chr_ram = if ines.ineschr == 0 {
+            let size = (ines.chr_ram_size + ines.chr_nvram_size).next_multiple_of(CHR_WINDOW);
+            vec![0; size.max(0x2000)]
+        } else {
+            Vec::new()
+        };
+        let mut prg_ram = vec![0; ines.prg_ram_size + ines.prg_nvram_size];
+        // The trainer goes to $7000, wherever that is in smaller RAM
+        if let Some(trainer) = ines.trainer.as_ref()
+            && !prg_ram.is_empty()
+        {
+            let start = 0x1000 % prg_ram.len();
+            if let Some(window) = prg_ram.get_mut(start..start + trainer.len()) {
+                window.copy_from_slice(trainer);
+            }
+        }
+        let mut state = Self::with_memory(ines, memory, mapper);
+        state.chr_ram = chr_ram;
+        state.prg_ram = prg_ram;
+        state.ppu_state.mirroring = mirroring;
+        state.update_mapper();
+        if let Some(path) = state.save_path() {
+            match fs::read(&path) {
+                Ok(save) => {
+                    if let Err(e) = state.import_save(&save) {
+                        log::warn!("ignoring {}: {e}", path.display());
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => log::warn!("failed to read {}: {e}", path.display()),
+            }
+        }
+        state.reset();
+        debug!("setting PC to ${:04X}", state.pc);
+        Ok(state)
+    }
+
+    /// A bare CPU with 64KiB of RAM filling the whole CPU bus and no other
+    /// devices, for testing the CPU in isolation. Does not go through reset.
+    pub fn new_flat_ram() -> Self {
+        let memory = vec![MemoryMap {
+            memory_regions: vec![MemoryRegion {
+                address_space: AddressSpace::Cpu,
+                range: 0x0000..=0xFFFF,
+            }],
+            device: Device::Ram(vec![0; 0x10000]),
+        }];
+        let ines = Ines::default();
+        let mapper = Box::new(mappers::Nrom::new(&ines));
+        Self::with_memory(ines, memory, mapper)
+    }
+
+    /// The devices on both buses, in lookup order.
+    pub fn memory(&self) -> &[MemoryMap] {
+        &self.memory
+    }
+
+    /// Maps a device to `range`, taking precedence over what is already there.
+    pub fn map_device(
+        &mut self,
+        address_space: AddressSpace,
+        range: RangeInclusive<u16>,
+        device: Box<dyn BusDevice>,
+    ) {
+        self.memory.insert(
+            0,
+            MemoryMap {
+                memory_regions: vec![MemoryRegion {
+                    address_space,
+                    range,
+                }],
+                device: Device::Custom(device),
+            },
+        );
+        self.rebuild_page_table();
+    }
+
+    /// Has to be called whenever the regions in `memory` change.
+    fn rebuild_page_table(&mut self) {
+        self.page_table = PageTable::new(&self.memory);
+    }
 
-        let mut state = Self {
+    /// Power-up state of the registers.
+    fn with_memory(ines: Ines, memory: Vec<MemoryMap>, mapper: Box<dyn Mapper>) -> Self {
+        let pc = 0;
+        let x = 0;
+        let a = 0;
+        let y = 0;
+        let sr = 0;
+        // The reset sequence brings this down to $FD
+        let sp = 0x00;
+        let cycles = 0;
+        let magic = 0xEE;
+        let ppu_state = PpuState::new();
+        Self {
             pc,
             a,
             x,
@@ -252,13 +532,72 @@ impl State {
             sr,
             sp,
             cycles,
+            magic,
+            nmi_pending: false,
+            irq_lines: 0,
+            stale_i: None,
+            trace: None,
             ines,
+            page_table: PageTable::new(&memory),
             memory,
             ppu_state,
+            controllers: Default::default(),
+            apu: Apu::new(),
+            mapper,
+            banks: Banks::default(),
+            chr_ram: Vec::new(),
+            prg_ram: Vec::new(),
+        }
+    }
+
+    /// Replaces the mapper, e.g. to enable bus conflicts or plug in one that
+    /// the registry does not know.
+    pub fn set_mapper(&mut self, mapper: Box<dyn Mapper>) {
+        self.mapper = mapper;
+        self.update_mapper();
+    }
+
+    /// Picks up the banks, mirroring and IRQ after the mapper changed.
+    fn update_mapper(&mut self) {
+        let prg_banks = (self.ines.inesprg as usize * 0x4000 / PRG_WINDOW).max(1);
+        self.banks.prg = self
+            .mapper
+            .prg_banks()
+            .map(|bank| bank % prg_banks * PRG_WINDOW);
+        self.banks.chr = if self.chr_ram.is_empty() {
+            // CHR-ROM comes after the PRG-ROM
+            let prg_size = self.ines.inesprg as usize * 0x4000;
+            let chr_banks = (self.ines.ineschr as usize * 0x2000 / CHR_WINDOW).max(1);
+            self.mapper
+                .chr_banks()
+                .map(|bank| prg_size + bank % chr_banks * CHR_WINDOW)
+        } else {
+            let chr_banks = self.chr_ram.len() / CHR_WINDOW;
+            self.mapper
+                .chr_banks()
+                .map(|bank| bank % chr_banks * CHR_WINDOW)
         };
-        state.reset();
-        debug!("setting PC to ${:04X}", state.pc);
-        state
+        if let Some(mirroring) = self.mapper.mirroring() {
+            self.ppu_state.mirroring = mirroring;
+        }
+        self.set_irq(IrqSource::Mapper, self.mapper.irq());
+    }
+
+    /// The PRG-ROM byte visible at `address` in $8000-$FFFF.
+    fn prg_byte(&self, address: u16) -> u8 {
+        let offset = (address - 0x8000) as usize;
+        // This means supplied ROM does not have to be filled
+        self.ines
+            .banks
+            .get(self.banks.prg[offset / PRG_WINDOW] + offset % PRG_WINDOW)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Where `address` in $0000-$1FFF on the PPU bus is in CHR-ROM or CHR-RAM.
+    fn chr_offset(&self, address: u16) -> usize {
+        let offset = (address & 0x1FFF) as usize;
+        self.banks.chr[offset / CHR_WINDOW] + offset % CHR_WINDOW
     }
 
     pub fn set_flag(&mut self, flag: u8, value: bool) {
@@ -273,24 +612,167 @@ impl State {
         (self.sr & flag) == flag
     }
 
-    pub fn run_one_instruction(&mut self) {
+    /// Signals an NMI, it is serviced before the next instruction.
+    pub fn trigger_nmi(&mut self) {
+        self.nmi_pending = true;
+    }
+
+    /// Asserts or releases the IRQ line on behalf of `source`.
+    pub fn set_irq(&mut self, source: IrqSource, active: bool) {
+        if active {
+            self.irq_lines |= source.bit();
+        } else {
+            self.irq_lines &= !source.bit();
+        }
+    }
+
+    /// Is anything holding the IRQ line?
+    pub fn irq_asserted(&self) -> bool {
+        self.irq_lines != 0
+    }
+
+    /// Sets all buttons held on the controller in `port`, see
+    /// `controller::buttons`.
+    pub fn set_buttons(&mut self, port: Port, buttons: u8) {
+        self.controllers[port.index()].buttons = buttons;
+    }
+
+    /// Presses or releases one button on the controller in `port`.
+    pub fn set_button(&mut self, port: Port, button: u8, pressed: bool) {
+        let controller = &mut self.controllers[port.index()];
+        if pressed {
+            controller.buttons |= button;
+        } else {
+            controller.buttons &= !button;
+        }
+    }
+
+    /// Where battery-backed PRG-RAM is saved, next to the ROM or assembly
+    /// file. `None` without a battery or a known source file.
+    pub fn save_path(&self) -> Option<PathBuf> {
+        if !self.ines.battery {
+            return None;
+        }
+        let source = self.ines.metadata.as_ref()?.data_source.as_ref()?;
+        Some(source.with_extension("sav"))
+    }
+
+    /// The contents of PRG-RAM, as stored in a .sav file.
+    pub fn export_save(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    /// Replaces PRG-RAM, `save` has to be exactly as large.
+    pub fn import_save(&mut self, save: &[u8]) -> io::Result<()> {
+        if save.len() != self.prg_ram.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "save is {} bytes, expected {}",
+                    save.len(),
+                    self.prg_ram.len()
+                ),
+            ));
+        }
+        self.prg_ram.copy_from_slice(save);
+        Ok(())
+    }
+
+    /// Flushes PRG-RAM to `save_path`, does nothing without a battery.
+    pub fn write_save(&self) -> io::Result<()> {
+        match self.save_path() {
+            Some(path) => fs::write(path, self.export_save()),
+            None => Ok(()),
+        }
+    }
+
+    /// Takes the audio samples produced since the last call, at
+    /// `apu.sample_rate` samples per second.
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        self.apu.take_samples()
+    }
+
+    /// Runs one instruction, or services a pending interrupt instead.
+    /// The PPU and devices are then run for the cycles it took.
+    /// On error PC is left at the offending instruction.
+    pub fn run_one_instruction(&mut self) -> Result<(), ExecutionError> {
+        let cycles_before = self.cycles;
+        let result = self.execute();
+        self.catch_up(self.cycles - cycles_before);
+        result
+    }
+
+    fn execute(&mut self) -> Result<(), ExecutionError> {
+        if interrupts::poll(self) {
+            debug!("interrupt, jumping to ${:04X}", self.pc);
+            return Ok(());
+        }
+        if self.trace.is_some() {
+            let line = trace::trace_line(self);
+            if let Some(trace) = self.trace.as_mut()
+                && let Err(e) = writeln!(trace, "{line}")
+            {
+                log::error!("failed to write trace, disabling it: {e}");
+                self.trace = None;
+            }
+        }
+        let pc = self.pc;
         let instr = self.read(self.pc, false);
         let Codepoint {
             opcode,
             addressing_mode,
+            cycles,
         } = CODEPOINTS[instr as usize].clone();
         debug!(
             "running {:?} {:?} at ${:04X}",
             opcode, addressing_mode, self.pc
         );
-        let memory_target = addressing_modes::run(addressing_mode, self);
-        opcodes::run(opcode, self, memory_target);
+        let delays_i = matches!(opcode, Opcode::CLI | Opcode::SEI | Opcode::PLP);
+        let i = self.get_flag(flags::I);
+        self.cycles += cycles as u64;
+        addressing_modes::run(addressing_mode, &opcode, self)
+            .and_then(|memory_target| opcodes::run(opcode, self, memory_target))
+            .map(|()| {
+                if delays_i {
+                    self.stale_i = Some(i);
+                }
+            })
+            .map_err(|reason| {
+                self.pc = pc;
+                ExecutionError {
+                    pc,
+                    opcode: instr,
+                    reason,
+                }
+            })
     }
 
-    pub fn run_instructions(&mut self, n_instructions: u64) {
+    /// Runs everything else on the bus for `cycles` CPU cycles.
+    fn catch_up(&mut self, cycles: u64) {
+        ppu::step(self, cycles);
+        apu::step(self, cycles);
+        for m in self.memory.iter_mut() {
+            if let Device::Custom(device) = &mut m.device {
+                device.tick(cycles);
+            }
+        }
+    }
+
+    /// Runs until the PPU has completed `n_frames` more frames.
+    pub fn run_frames(&mut self, n_frames: u64) -> Result<(), ExecutionError> {
+        let frame = self.ppu_state.frame + n_frames;
+        while self.ppu_state.frame < frame {
+            self.run_one_instruction()?;
+        }
+        Ok(())
+    }
+
+    /// Stops at the first instruction that fails.
+    pub fn run_instructions(&mut self, n_instructions: u64) -> Result<(), ExecutionError> {
         for _ in 0..n_instructions {
-            self.run_one_instruction();
+            self.run_one_instruction()?;
         }
+        Ok(())
     }
 
     pub fn read(&mut self, address: u16, read_only: bool) -> u8 {
@@ -307,13 +789,35 @@ impl State {
     }
 
     fn write_to_bus(&mut self, address: u16, value: u8, bus: AddressSpace) {
-        debug!("write: {:#06X}", address);
-        if let Some((d, r)) = try_address(&mut self.memory, bus, address) {
+        if bus == AddressSpace::Ppu {
+            self.ppu_access(address);
+        }
+        if let Some((d, start)) = decode(&mut self.memory, &self.page_table, bus, address) {
             match d {
                 Device::Ram(bytes) => {
-                    bytes[address as usize - *r.start() as usize] = value;
+                    bytes[(address - start) as usize] = value;
+                }
+                Device::Prg => {
+                    let value = if self.mapper.bus_conflicts() {
+                        value & self.prg_byte(address)
+                    } else {
+                        value
+                    };
+                    self.mapper.write(address, value, self.cycles);
+                    self.update_mapper();
+                }
+                Device::Chr => {
+                    let offset = self.chr_offset(address);
+                    if let Some(byte) = self.chr_ram.get_mut(offset) {
+                        *byte = value;
+                    }
+                }
+                Device::PrgRam => {
+                    let len = self.prg_ram.len();
+                    if len != 0 && self.mapper.prg_ram_writable() {
+                        self.prg_ram[(address - start) as usize % len] = value;
+                    }
                 }
-                Device::Rom(_bytes) => {}
                 Device::Palette(bs) => {
                     let address = address & 0x001F;
                     // a sprite's "transparent color"
@@ -327,22 +831,18 @@ impl State {
                     };
                     bs[address as usize] = value;
                 }
-                Device::PpuRegisters => match address & 0b111 {
-                    0x0006 => match self.ppu_state.tmp_addr {
-                        None => {
-                            self.ppu_state.tmp_addr = Some(address & 0xFF00);
-                        }
-                        Some(x) => {
-                            self.ppu_state.tmp_addr = Some(x | (address & 0x00FF));
-                        }
-                    },
-                    0x0007 => {
-                        if let Some(address) = self.ppu_state.tmp_addr {
-                            self.ppu_write(address, value);
-                        } else {
-                            log::warn!(
-                                "expecting an address to be put in the ppu address register"
-                            );
+                Device::Nametables => {
+                    let ppu = &mut self.ppu_state;
+                    ppu.vram[ppu.mirroring.vram_offset(address)] = value;
+                }
+                Device::Custom(device) => device.write(address - start, value),
+                Device::PpuRegisters => ppu::write_register(self, address, value),
+                Device::IoRegisters => match address {
+                    0x4000..=0x4013 | 0x4015 | 0x4017 => apu::write_register(self, address, value),
+                    0x4014 => ppu::oam_dma(self, value),
+                    0x4016 => {
+                        for controller in self.controllers.iter_mut() {
+                            controller.write_strobe(value & 1 != 0);
                         }
                     }
                     _ => {}
@@ -353,19 +853,24 @@ impl State {
 
     /// If "read_only" is set, the read has no affect on the state of the system.
     fn read_from_bus(&mut self, address: u16, read_only: bool, bus: AddressSpace) -> u8 {
-        if !read_only {
-            debug!("read: {:#06X}", address);
+        if bus == AddressSpace::Ppu && !read_only {
+            self.ppu_access(address);
         }
-        if let Some((d, r)) = try_address(&mut self.memory, bus, address) {
+        if let Some((d, start)) = decode(&mut self.memory, &self.page_table, bus, address) {
             match d {
-                Device::Ram(bytes) => bytes[address as usize - *r.start() as usize],
-                Device::Rom(i) => {
-                    let index = address as usize - *r.start() as usize;
-                    // This means supplied ROM does not have to be filled
-                    if index < BANK_SIZE {
-                        self.ines.banks[BANK_SIZE * *i + index]
+                Device::Ram(bytes) => bytes[(address - start) as usize],
+                Device::Prg => self.prg_byte(address),
+                Device::PrgRam if !self.prg_ram.is_empty() && self.mapper.prg_ram_enabled() => {
+                    self.prg_ram[(address - start) as usize % self.prg_ram.len()]
+                }
+                // Open bus
+                Device::PrgRam => 0,
+                Device::Chr => {
+                    let offset = self.chr_offset(address);
+                    if self.chr_ram.is_empty() {
+                        self.ines.banks.get(offset).copied().unwrap_or(0)
                     } else {
-                        0
+                        self.chr_ram[offset]
                     }
                 }
                 Device::Palette(bs) => {
@@ -381,63 +886,53 @@ impl State {
                     };
                     bs[address as usize]
                 }
-                Device::PpuRegisters => {
-                    match address & 0b111 {
-                        // PPUSTATUS, PPU Status register
-                        0x0002 => {
-                            // TODO remove this
-                            self.ppu_state.vblank = true;
-                            let to_return = (if self.ppu_state.vblank { 1 << 7 } else { 0 })
-                                | (if self.ppu_state.sprite_0_hit {
-                                    1 << 6
-                                } else {
-                                    0
-                                })
-                                | (if self.ppu_state.sprite_overflow {
-                                    1 << 5
-                                } else {
-                                    0
-                                });
-                            if !read_only {
-                                self.ppu_state.vblank = false;
-                                self.ppu_state.tmp_addr = None;
-                            }
-                            to_return
-                        }
-                        // PPUDATA, VRAM Data Read/Write
-                        0x0007 => {
-                            let mut to_return = self.ppu_state.tmp_val;
-                            if let Some(tmp_addr) = self.ppu_state.tmp_addr {
-                                if !read_only {
-                                    self.ppu_state.tmp_val = self.ppu_read(tmp_addr, false);
-                                }
-                                // Special cases for palettes (gets value in the same read)
-                                if tmp_addr == 0x3F00 {
-                                    to_return = self.ppu_state.tmp_val;
-                                }
-                            }
-                            to_return
-                        }
-                        _ => 0,
-                    }
-                    //registers[address]
+                Device::Nametables => {
+                    self.ppu_state.vram[self.ppu_state.mirroring.vram_offset(address)]
                 }
+                Device::Custom(device) if read_only => device.peek(address - start),
+                Device::Custom(device) => device.read(address - start),
+                Device::PpuRegisters => ppu::read_register(self, address, read_only),
+                // The upper bits are open bus, usually the $40 of the address
+                Device::IoRegisters => match address {
+                    0x4015 => apu::read_status(self, read_only),
+                    0x4016 => 0x40 | self.controllers[Port::One.index()].read(read_only),
+                    0x4017 => 0x40 | self.controllers[Port::Two.index()].read(read_only),
+                    _ => 0,
+                },
             }
         } else {
             0
         }
     }
 
+    /// Lets the mapper watch the PPU address bus.
+    fn ppu_access(&mut self, address: u16) {
+        self.mapper.ppu_access(address, self.ppu_state.dots);
+        self.set_irq(IrqSource::Mapper, self.mapper.irq());
+    }
+
     pub fn read_u16(&mut self, val: u16) -> u16 {
         let lo = self.read(val, false) as u16;
         let hi = self.read(val + 1, false) as u16;
         (hi << 8) | lo
     }
 
-    /// A soft reset.
+    /// A soft reset, goes through the reset sequence without writing to the stack.
     pub fn reset(&mut self) {
+        self.sp = self.sp.wrapping_sub(3);
+        self.set_flag(flags::I, true);
+        self.set_flag(flags::U, true);
+        self.nmi_pending = false;
+        self.stale_i = None;
         let new_pc: u16 = self.read_u16(shared::vectors::RESET);
         self.pc = new_pc;
+        self.ppu_state.ctrl = 0;
+        self.ppu_state.mask = 0;
+        self.ppu_state.w = false;
+        // Silences all channels
+        apu::write_register(self, 0x4015, 0);
+        self.cycles += interrupts::INTERRUPT_CYCLES;
+        self.catch_up(interrupts::INTERRUPT_CYCLES);
     }
 
     /// Helper.
@@ -447,17 +942,35 @@ impl State {
     }
 }
 
-/// Helper function to get the device and the range
+/// Helper function to get the device and the start of its region
+fn decode<'a>(
+    memory: &'a mut [MemoryMap],
+    page_table: &PageTable,
+    address_space: AddressSpace,
+    address: u16,
+) -> Option<(&'a mut Device, u16)> {
+    let pages = match address_space {
+        AddressSpace::Cpu => &page_table.cpu,
+        AddressSpace::Ppu => &page_table.ppu,
+    };
+    match pages[address as usize / PAGE_SIZE] {
+        Page::Unmapped => None,
+        Page::Mapped { map, start } => Some((&mut memory[map].device, start)),
+        Page::Split => try_address(memory, address_space, address),
+    }
+}
+
+/// Linear lookup, for pages that are shared between regions
 fn try_address(
-    memory: &mut Vec<MemoryMap>,
+    memory: &mut [MemoryMap],
     address_space: AddressSpace,
     address: u16,
-) -> Option<(&mut Device, RangeInclusive<u16>)> {
+) -> Option<(&mut Device, u16)> {
     memory.iter_mut().find_map(|m| {
         m.memory_regions
             .iter()
             .find(|mr| mr.address_space == address_space && mr.range.contains(&address))
-            .map(|mr| (&mut m.device, mr.range.clone()))
+            .map(|mr| (&mut m.device, *mr.range.start()))
     })
 }
 
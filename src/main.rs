@@ -0,0 +1,379 @@
+//! Headless runner, loads a ROM or assembly file, runs it and reports the state.
+use std::env;
+use std::fs::File;
+use std::io::BufWriter;
+use std::ops::RangeInclusive;
+use std::process::ExitCode;
+
+use remun::State;
+use remun::apu;
+use remun::ppu;
+use remun::wav;
+
+const USAGE: &str = "usage: remun <file.nes|file.asm> (--instructions N | --cycles N | --frames N)
+             [--dump START-END]... [--trace FILE] [--png FILE]
+             [--wav FILE [--stems] [--sample-rate N]]
+
+  --instructions N   run N instructions
+  --cycles N         run until N CPU cycles have passed
+  --frames N         run until the PPU has completed N frames
+  --dump START-END   print memory on the CPU bus, can be given several times
+  --trace FILE       write a nestest-style log of every instruction to FILE
+  --png FILE         save the last rendered frame as a PNG image
+  --wav FILE         record the mixed audio output to a WAV file
+  --stems            with --wav, also record every channel to FILE-<channel>.wav
+  --sample-rate N    audio sample rate for --wav, 44100 by default
+
+numbers are decimal, or hexadecimal with a $ or 0x prefix";
+
+/// When to stop running.
+enum Limit {
+    Instructions(u64),
+    Cycles(u64),
+    Frames(u64),
+}
+
+struct Options {
+    path: String,
+    limit: Limit,
+    dumps: Vec<RangeInclusive<u16>>,
+    trace: Option<String>,
+    png: Option<String>,
+    wav: Option<String>,
+    stems: bool,
+    sample_rate: u32,
+}
+
+/// Audio collected while running, for --wav.
+struct Recording {
+    mixed: Vec<f32>,
+    channels: [Vec<f32>; 5],
+}
+
+impl Recording {
+    /// Takes the samples out of the APU before its buffer fills up.
+    fn collect(&mut self, state: &mut State) {
+        self.mixed.append(&mut state.take_samples());
+        for (recorded, mut new) in self
+            .channels
+            .iter_mut()
+            .zip(state.apu.take_channel_samples())
+        {
+            recorded.append(&mut new);
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    pretty_env_logger::init();
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("remun, headless NES runner\n\n{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut state = match remun::load_from_file(&options.path).and_then(State::new) {
+        Ok(state) => state,
+        Err(e) => {
+            eprintln!("failed to load {}: {e}", options.path);
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Some(path) = options.trace.as_ref() {
+        match File::create(path) {
+            Ok(file) => state.trace = Some(Box::new(BufWriter::new(file))),
+            Err(e) => {
+                eprintln!("failed to create {path}: {e}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    let mut recording = options.wav.as_ref().map(|_| {
+        state.apu.sample_rate = options.sample_rate;
+        state.apu.record_channels = options.stems;
+        // Drop what was produced during reset
+        state.take_samples();
+        state.apu.take_channel_samples();
+        Recording {
+            mixed: Vec::new(),
+            channels: Default::default(),
+        }
+    });
+    let result = run(&mut state, &options.limit, recording.as_mut());
+    if let Err(e) = state.write_save() {
+        eprintln!("failed to write the save file: {e}");
+    }
+    print_registers(&state);
+    for range in options.dumps.iter() {
+        dump(&mut state, range.clone());
+    }
+    if let Some(path) = options.png.as_ref()
+        && let Err(e) = save_png(&state, path)
+    {
+        eprintln!("failed to save {path}: {e}");
+        return ExitCode::FAILURE;
+    }
+    if let (Some(path), Some(recording)) = (options.wav.as_ref(), recording.as_ref())
+        && let Err(e) = save_recording(recording, path, options.stems, options.sample_rate)
+    {
+        eprintln!("failed to save {path}: {e}");
+        return ExitCode::FAILURE;
+    }
+    if let Err(e) = result {
+        eprintln!("{e}");
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+fn run(
+    state: &mut State,
+    limit: &Limit,
+    recording: Option<&mut Recording>,
+) -> Result<(), remun::ExecutionError> {
+    let Some(recording) = recording else {
+        return match *limit {
+            Limit::Instructions(n) => state.run_instructions(n),
+            Limit::Cycles(n) => run_until(state, n),
+            Limit::Frames(n) => state.run_frames(n),
+        };
+    };
+    // Collected every frame, before the APU starts dropping samples
+    let start_frame = state.ppu_state.frame;
+    let mut instructions = 0;
+    let result = loop {
+        let done = match *limit {
+            Limit::Instructions(n) => instructions >= n,
+            Limit::Cycles(n) => state.cycles >= n,
+            Limit::Frames(n) => state.ppu_state.frame >= start_frame + n,
+        };
+        if done {
+            break Ok(());
+        }
+        let frame = state.ppu_state.frame;
+        if let Err(e) = state.run_one_instruction() {
+            break Err(e);
+        }
+        instructions += 1;
+        if state.ppu_state.frame != frame {
+            recording.collect(state);
+        }
+    };
+    recording.collect(state);
+    result
+}
+
+fn run_until(state: &mut State, cycles: u64) -> Result<(), remun::ExecutionError> {
+    while state.cycles < cycles {
+        state.run_one_instruction()?;
+    }
+    Ok(())
+}
+
+fn print_registers(state: &State) {
+    println!(
+        "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+        state.pc, state.a, state.x, state.y, state.sr, state.sp, state.cycles
+    );
+}
+
+fn save_png(state: &State, path: &str) -> image::ImageResult<()> {
+    image::save_buffer(
+        path,
+        &ppu::to_rgba(&state.ppu_state.framebuffer[..]),
+        ppu::WIDTH as u32,
+        ppu::HEIGHT as u32,
+        image::ColorType::Rgba8,
+    )
+}
+
+/// Saves the mix to `path`, and with `stems` every channel next to it.
+fn save_recording(
+    recording: &Recording,
+    path: &str,
+    stems: bool,
+    sample_rate: u32,
+) -> std::io::Result<()> {
+    wav::save_wav(path, sample_rate, &recording.mixed)?;
+    if stems {
+        let base = path.strip_suffix(".wav").unwrap_or(path);
+        for (name, samples) in apu::CHANNELS.iter().zip(recording.channels.iter()) {
+            wav::save_wav(format!("{base}-{name}.wav"), sample_rate, samples)?;
+        }
+    }
+    Ok(())
+}
+
+/// Prints memory 16 bytes per line, reads do not affect the state.
+fn dump(state: &mut State, range: RangeInclusive<u16>) {
+    let addresses: Vec<u16> = range.collect();
+    for line in addresses.chunks(16) {
+        let bytes: Vec<String> = line
+            .iter()
+            .map(|a| format!("{:02X}", state.read(*a, true)))
+            .collect();
+        println!("{:04X}: {}", line[0], bytes.join(" "));
+    }
+}
+
+/// `None` when help was asked for.
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut path = None;
+    let mut limit = None;
+    let mut dumps = Vec::new();
+    let mut trace = None;
+    let mut png = None;
+    let mut wav = None;
+    let mut stems = false;
+    let mut sample_rate = apu::DEFAULT_SAMPLE_RATE;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("missing value for {arg}"));
+        match arg.as_str() {
+            "--instructions" => limit = Some(Limit::Instructions(parse_number(&value()?)?)),
+            "--cycles" => limit = Some(Limit::Cycles(parse_number(&value()?)?)),
+            "--frames" => limit = Some(Limit::Frames(parse_number(&value()?)?)),
+            "--dump" => dumps.push(parse_range(&value()?)?),
+            "--trace" => trace = Some(value()?),
+            "--png" => png = Some(value()?),
+            "--wav" => wav = Some(value()?),
+            "--stems" => stems = true,
+            "--sample-rate" => {
+                sample_rate = parse_number(&value()?)?
+                    .try_into()
+                    .ok()
+                    .filter(|rate| *rate > 0)
+                    .ok_or("invalid sample rate")?
+            }
+            "-h" | "--help" => return Ok(None),
+            a if a.starts_with("--") => return Err(format!("unknown option: {a}")),
+            _ if path.is_some() => return Err(format!("unexpected argument: {arg}")),
+            _ => path = Some(arg),
+        }
+    }
+    if stems && wav.is_none() {
+        return Err(String::from("--stems needs --wav"));
+    }
+    Ok(Some(Options {
+        path: path.ok_or("no file given")?,
+        limit: limit.ok_or("need one of --instructions, --cycles or --frames")?,
+        dumps,
+        trace,
+        png,
+        wav,
+        stems,
+        sample_rate,
+    }))
+}
+
+fn parse_number(s: &str) -> Result<u64, String> {
+    let parsed = if let Some(hex) = s.strip_prefix('$').or(s.strip_prefix("0x")) {
+        u64::from_str_radix(hex, 16)
+    } else {
+        s.parse()
+    };
+    parsed.map_err(|e| format!("invalid number '{s}': {e}"))
+}
+
+fn parse_range(s: &str) -> Result<RangeInclusive<u16>, String> {
+    let (start, end) = s
+        .split_once('-')
+        .ok_or(format!("expected a range like $0000-$07FF, got '{s}'"))?;
+    let to_address =
+        |n: u64| u16::try_from(n).map_err(|_| format!("address out of range in '{s}'"));
+    let start = to_address(parse_number(start)?)?;
+    let end = to_address(parse_number(end)?)?;
+    if start > end {
+        return Err(format!("range '{s}' is backwards"));
+    }
+    Ok(start..=end)
+}
+
+#[cfg(test)]
+mod test_args {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<Options>, String> {
+        parse_args(args.iter().map(|a| a.to_string()))
+    }
+
+    fn error(args: &[&str]) -> String {
+        match parse(args) {
+            Err(e) => e,
+            Ok(_) => panic!("expected {args:?} to fail"),
+        }
+    }
+
+    #[test]
+    fn test_options() {
+        let options = parse(&[
+            "game.nes",
+            "--frames",
+            "$10",
+            "--dump",
+            "0x0200-$02FF",
+            "--wav",
+            "out.wav",
+            "--stems",
+        ])
+        .unwrap()
+        .unwrap();
+        assert_eq!(options.path, "game.nes");
+        assert!(matches!(options.limit, Limit::Frames(16)));
+        assert_eq!(options.dumps, [0x0200..=0x02FF]);
+        assert_eq!(options.wav.as_deref(), Some("out.wav"));
+        assert!(options.stems);
+        assert_eq!(options.sample_rate, apu::DEFAULT_SAMPLE_RATE);
+    }
+
+    #[test]
+    fn test_help() {
+        assert!(parse(&["--help"]).unwrap().is_none());
+        assert!(parse(&["game.nes", "-h"]).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            error(&["game.nes", "--frames"]),
+            "missing value for --frames"
+        );
+        assert_eq!(error(&["game.nes", "--fast"]), "unknown option: --fast");
+        assert_eq!(error(&["a.nes", "b.nes"]), "unexpected argument: b.nes");
+        assert_eq!(error(&["--frames", "1"]), "no file given");
+        assert_eq!(
+            error(&["game.nes"]),
+            "need one of --instructions, --cycles or --frames"
+        );
+        assert_eq!(
+            error(&["game.nes", "--frames", "1", "--stems"]),
+            "--stems needs --wav"
+        );
+    }
+
+    #[test]
+    fn test_numbers() {
+        assert_eq!(parse_number("42"), Ok(42));
+        assert_eq!(parse_number("$2A"), Ok(42));
+        assert_eq!(parse_number("0x2a"), Ok(42));
+        assert!(parse_number("$").is_err());
+        assert!(parse_number("-1").is_err());
+        assert!(error(&["game.nes", "--cycles", "ten"]).starts_with("invalid number 'ten'"));
+        assert_eq!(parse_range("$10-$1F"), Ok(0x10..=0x1F));
+        assert_eq!(
+            parse_range("$1F-$10"),
+            Err(String::from("range '$1F-$10' is backwards"))
+        );
+        assert_eq!(
+            parse_range("$10000-$10001"),
+            Err(String::from("address out of range in '$10000-$10001'"))
+        );
+        assert!(
+            error(&["game.nes", "--frames", "1", "--sample-rate", "0"]).contains("sample rate")
+        );
+    }
+}
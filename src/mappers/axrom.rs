@@ -0,0 +1,45 @@
+//! AxROM (mapper 7), a switchable 32KiB bank and single-screen mirroring,
+//! https://www.nesdev.org/wiki/AxROM
+use super::Mapper;
+use crate::ppu::Mirroring;
+
+pub struct AxRom {
+    /// 32KiB bank in bits 0-2, bit 4 selects the nametable.
+    register: u8,
+    bus_conflicts: bool,
+}
+
+impl AxRom {
+    pub fn new(bus_conflicts: bool) -> Self {
+        Self {
+            register: 0,
+            bus_conflicts,
+        }
+    }
+}
+
+impl Mapper for AxRom {
+    fn write(&mut self, _address: u16, value: u8, _cycle: u64) {
+        self.register = value;
+    }
+
+    fn prg_banks(&self) -> [usize; 4] {
+        std::array::from_fn(|i| (self.register & 0x07) as usize * 4 + i)
+    }
+
+    fn chr_banks(&self) -> [usize; 8] {
+        [0, 1, 2, 3, 4, 5, 6, 7]
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(if self.register & 0x10 == 0 {
+            Mirroring::SingleScreenLower
+        } else {
+            Mirroring::SingleScreenUpper
+        })
+    }
+
+    fn bus_conflicts(&self) -> bool {
+        self.bus_conflicts
+    }
+}
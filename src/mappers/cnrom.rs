@@ -0,0 +1,44 @@
+//! CNROM (mapper 3), fixed PRG and a switchable 8KiB CHR bank,
+//! https://www.nesdev.org/wiki/CNROM
+use shared::Ines;
+
+use super::Mapper;
+
+pub struct CnRom {
+    bank: u8,
+    /// 16KiB of PRG-ROM mirrored at $C000, like NROM-128.
+    mirrored: bool,
+    bus_conflicts: bool,
+}
+
+impl CnRom {
+    pub fn new(ines: &Ines, bus_conflicts: bool) -> Self {
+        Self {
+            bank: 0,
+            mirrored: ines.inesprg == 1,
+            bus_conflicts,
+        }
+    }
+}
+
+impl Mapper for CnRom {
+    fn write(&mut self, _address: u16, value: u8, _cycle: u64) {
+        self.bank = value;
+    }
+
+    fn prg_banks(&self) -> [usize; 4] {
+        if self.mirrored {
+            [0, 1, 0, 1]
+        } else {
+            [0, 1, 2, 3]
+        }
+    }
+
+    fn chr_banks(&self) -> [usize; 8] {
+        std::array::from_fn(|i| self.bank as usize * 8 + i)
+    }
+
+    fn bus_conflicts(&self) -> bool {
+        self.bus_conflicts
+    }
+}
@@ -0,0 +1,122 @@
+//! MMC1 (mapper 1), registers written one bit at a time through a shift
+//! register, https://www.nesdev.org/wiki/MMC1
+use shared::Ines;
+
+use super::Mapper;
+use crate::ppu::Mirroring;
+
+pub struct Mmc1 {
+    /// Bits written so far, the first one ends up lowest.
+    shift: u8,
+    /// Number of bits in `shift`, the fifth write copies it to a register.
+    shift_count: u8,
+    /// Mirroring in bits 0-1, PRG mode in 2-3 and CHR mode in 4.
+    control: u8,
+    /// The mirroring bits are only used once control has been written, until
+    /// then the header mirroring applies.
+    control_written: bool,
+    chr_bank_0: u8,
+    chr_bank_1: u8,
+    /// 16KiB bank in bits 0-3, bit 4 disables PRG-RAM.
+    prg_bank: u8,
+    /// Number of 16KiB PRG-ROM banks.
+    prg_banks: usize,
+    /// Cycle of the last write, see `Mapper::write`.
+    last_write: Option<u64>,
+}
+
+impl Mmc1 {
+    pub fn new(ines: &Ines) -> Self {
+        Self {
+            shift: 0,
+            shift_count: 0,
+            // The last bank is fixed at $C000 on power-up
+            control: 0x0C,
+            control_written: false,
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0,
+            prg_banks: (ines.inesprg as usize).max(1),
+            last_write: None,
+        }
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn write(&mut self, address: u16, value: u8, cycle: u64) {
+        // Writes on consecutive cycles are ignored after the first, so only the
+        // first write of a read-modify-write instruction counts. Those all
+        // happen at the same `cycle` here.
+        let consecutive = self
+            .last_write
+            .is_some_and(|last| cycle.abs_diff(last) <= 1);
+        self.last_write = Some(cycle);
+        if consecutive {
+            return;
+        }
+        if value & 0x80 != 0 {
+            self.shift = 0;
+            self.shift_count = 0;
+            self.control |= 0x0C;
+            return;
+        }
+        self.shift |= (value & 1) << self.shift_count;
+        self.shift_count += 1;
+        if self.shift_count < 5 {
+            return;
+        }
+        match address {
+            0x8000..=0x9FFF => {
+                self.control = self.shift;
+                self.control_written = true;
+            }
+            0xA000..=0xBFFF => self.chr_bank_0 = self.shift,
+            0xC000..=0xDFFF => self.chr_bank_1 = self.shift,
+            _ => self.prg_bank = self.shift,
+        }
+        self.shift = 0;
+        self.shift_count = 0;
+    }
+
+    fn prg_banks(&self) -> [usize; 4] {
+        // 512KiB boards (SUROM) pick the 256KiB half with a CHR register bit
+        let outer = if self.prg_banks > 16 {
+            (self.chr_bank_0 & 0x10) as usize
+        } else {
+            0
+        };
+        let bank = outer + (self.prg_bank & 0x0F) as usize;
+        let (low, high) = match (self.control >> 2) & 0b11 {
+            0 | 1 => (bank & !1, bank | 1),
+            2 => (outer, bank),
+            _ => (bank, outer + (self.prg_banks - 1).min(15)),
+        };
+        [low * 2, low * 2 + 1, high * 2, high * 2 + 1]
+    }
+
+    fn chr_banks(&self) -> [usize; 8] {
+        let (low, high) = if self.control & 0x10 == 0 {
+            let bank = (self.chr_bank_0 & !1) as usize;
+            (bank, bank + 1)
+        } else {
+            (self.chr_bank_0 as usize, self.chr_bank_1 as usize)
+        };
+        std::array::from_fn(|i| if i < 4 { low * 4 + i } else { high * 4 + i - 4 })
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        if !self.control_written {
+            return None;
+        }
+        Some(match self.control & 0b11 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        })
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.prg_bank & 0x10 == 0
+    }
+}
@@ -0,0 +1,148 @@
+//! MMC3 (mapper 4), fine grained banking and a scanline counter clocked by
+//! PPU A12, https://www.nesdev.org/wiki/MMC3
+use shared::Ines;
+
+use super::Mapper;
+use crate::ppu::Mirroring;
+
+/// A12 has to stay low this many dots before a rise clocks the counter,
+/// like the M2 filter on the board. Fetches of one scanline never get there.
+const A12_LOW_DOTS: u64 = 10;
+
+pub struct Mmc3 {
+    /// Register written by the next $8001 write in bits 0-2, PRG mode in
+    /// bit 6 and CHR A12 inversion in bit 7.
+    bank_select: u8,
+    /// R0-R7, 2KiB CHR banks in R0 and R1, 1KiB in R2-R5, PRG in R6 and R7.
+    registers: [u8; 8],
+    /// Set by $A000, never changes a four-screen board.
+    mirroring: Option<Mirroring>,
+    four_screen: bool,
+    /// $A001, bit 7 enables PRG-RAM and bit 6 protects it from writes.
+    prg_ram_protect: u8,
+    /// Number of 8KiB PRG-ROM banks.
+    prg_banks: usize,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+    /// When A12 was last seen high, and if it went low since.
+    a12_high_dot: u64,
+    a12_low: bool,
+}
+
+impl Mmc3 {
+    pub fn new(ines: &Ines) -> Self {
+        Self {
+            bank_select: 0,
+            registers: [0, 2, 4, 5, 6, 7, 0, 1],
+            mirroring: None,
+            four_screen: Mirroring::from_ines(ines.mirroring) == Mirroring::FourScreen,
+            prg_ram_protect: 0x80,
+            prg_banks: (ines.inesprg as usize * 2).max(2),
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            a12_high_dot: 0,
+            a12_low: true,
+        }
+    }
+
+    fn clock_counter(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn write(&mut self, address: u16, value: u8, _cycle: u64) {
+        let even = address & 1 == 0;
+        match (address, even) {
+            (0x8000..=0x9FFF, true) => self.bank_select = value,
+            (0x8000..=0x9FFF, false) => self.registers[(self.bank_select & 0b111) as usize] = value,
+            (0xA000..=0xBFFF, true) if !self.four_screen => {
+                self.mirroring = Some(if value & 1 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                });
+            }
+            (0xA000..=0xBFFF, true) => {}
+            (0xA000..=0xBFFF, false) => self.prg_ram_protect = value,
+            (0xC000..=0xDFFF, true) => self.irq_latch = value,
+            (0xC000..=0xDFFF, false) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            (_, true) => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            (_, false) => self.irq_enabled = true,
+        }
+    }
+
+    fn prg_banks(&self) -> [usize; 4] {
+        let second_last = self.prg_banks - 2;
+        let r6 = (self.registers[6] & 0x3F) as usize;
+        let r7 = (self.registers[7] & 0x3F) as usize;
+        if self.bank_select & 0x40 == 0 {
+            [r6, r7, second_last, second_last + 1]
+        } else {
+            [second_last, r7, r6, second_last + 1]
+        }
+    }
+
+    fn chr_banks(&self) -> [usize; 8] {
+        let r = self.registers.map(|r| r as usize);
+        let two_kib = [r[0] & !1, r[0] | 1, r[1] & !1, r[1] | 1];
+        let one_kib = [r[2], r[3], r[4], r[5]];
+        let mut banks = [0; 8];
+        if self.bank_select & 0x80 == 0 {
+            banks[..4].copy_from_slice(&two_kib);
+            banks[4..].copy_from_slice(&one_kib);
+        } else {
+            banks[..4].copy_from_slice(&one_kib);
+            banks[4..].copy_from_slice(&two_kib);
+        }
+        banks
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        self.mirroring
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn ppu_access(&mut self, address: u16, dot: u64) {
+        if address & 0x1000 == 0 {
+            self.a12_low = true;
+            return;
+        }
+        if self.a12_low && dot - self.a12_high_dot >= A12_LOW_DOTS {
+            self.clock_counter();
+        }
+        self.a12_low = false;
+        self.a12_high_dot = dot;
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.prg_ram_protect & 0x80 != 0
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect & 0xC0 == 0x80
+    }
+}
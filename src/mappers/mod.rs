@@ -0,0 +1,76 @@
+//! Cartridge hardware that switches banks, controls mirroring and raises IRQs,
+//! https://www.nesdev.org/wiki/Mapper
+use shared::Ines;
+
+use crate::FileError;
+use crate::ppu::Mirroring;
+
+mod axrom;
+mod cnrom;
+mod mmc1;
+mod mmc3;
+mod nrom;
+mod uxrom;
+
+pub use axrom::AxRom;
+pub use cnrom::CnRom;
+pub use mmc1::Mmc1;
+pub use mmc3::Mmc3;
+pub use nrom::Nrom;
+pub use uxrom::UxRom;
+
+/// Size of the PRG-ROM windows at $8000, $A000, $C000 and $E000.
+pub const PRG_WINDOW: usize = 0x2000;
+/// Size of the CHR windows, eight of them cover $0000-$1FFF on the PPU bus.
+pub const CHR_WINDOW: usize = 0x0400;
+
+/// The banking logic of a cartridge. `State` asks for the banks again after
+/// every write to $8000-$FFFF, bank numbers past the end of the ROM wrap.
+pub trait Mapper {
+    /// A CPU write to $8000-$FFFF, with bus conflicts already applied.
+    /// `cycle` is `State::cycles`, the same for every write of an instruction.
+    fn write(&mut self, address: u16, value: u8, cycle: u64);
+    /// The 8KiB PRG-ROM banks shown at $8000, $A000, $C000 and $E000.
+    fn prg_banks(&self) -> [usize; 4];
+    /// The 1KiB CHR banks shown at $0000-$1FFF, CHR-RAM is banked the same way.
+    fn chr_banks(&self) -> [usize; 8];
+    /// `None` leaves the mirroring from the header.
+    fn mirroring(&self) -> Option<Mirroring> {
+        None
+    }
+    /// Is the mapper holding the IRQ line?
+    fn irq(&self) -> bool {
+        false
+    }
+    /// Called for every PPU bus access that is not read only, `dot` counts
+    /// PPU dots since power-on.
+    fn ppu_access(&mut self, _address: u16, _dot: u64) {}
+    /// Can PRG-RAM at $6000-$7FFF be accessed?
+    fn prg_ram_enabled(&self) -> bool {
+        true
+    }
+    /// Can the CPU write to PRG-RAM, or is it protected?
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_enabled()
+    }
+    /// Writes to ROM are ANDed with the byte the ROM drives onto the bus.
+    fn bus_conflicts(&self) -> bool {
+        false
+    }
+}
+
+/// Picks the mapper for `Ines::mapper`. The discrete mappers only have bus
+/// conflicts when NES 2.0 submapper 2 asks for them, well-behaved games do
+/// not depend on them.
+pub fn from_ines(ines: &Ines) -> Result<Box<dyn Mapper>, FileError> {
+    let bus_conflicts = ines.submapper == 2;
+    match ines.mapper {
+        0 => Ok(Box::new(Nrom::new(ines))),
+        1 => Ok(Box::new(Mmc1::new(ines))),
+        2 => Ok(Box::new(UxRom::new(ines, bus_conflicts))),
+        3 => Ok(Box::new(CnRom::new(ines, bus_conflicts))),
+        4 => Ok(Box::new(Mmc3::new(ines))),
+        7 => Ok(Box::new(AxRom::new(bus_conflicts))),
+        mapper => Err(FileError::UnsupportedMapper(mapper)),
+    }
+}
@@ -0,0 +1,33 @@
+//! NROM (mapper 0), no banking, https://www.nesdev.org/wiki/NROM
+use shared::Ines;
+
+use super::Mapper;
+
+pub struct Nrom {
+    /// NROM-128 mirrors its 16KiB at $C000.
+    mirrored: bool,
+}
+
+impl Nrom {
+    pub fn new(ines: &Ines) -> Self {
+        Self {
+            mirrored: ines.inesprg == 1,
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn write(&mut self, _address: u16, _value: u8, _cycle: u64) {}
+
+    fn prg_banks(&self) -> [usize; 4] {
+        if self.mirrored {
+            [0, 1, 0, 1]
+        } else {
+            [0, 1, 2, 3]
+        }
+    }
+
+    fn chr_banks(&self) -> [usize; 8] {
+        [0, 1, 2, 3, 4, 5, 6, 7]
+    }
+}
@@ -0,0 +1,42 @@
+//! UxROM (mapper 2), a switchable 16KiB bank at $8000 and the last one fixed
+//! at $C000, https://www.nesdev.org/wiki/UxROM
+use shared::Ines;
+
+use super::Mapper;
+
+pub struct UxRom {
+    bank: u8,
+    /// Number of 16KiB PRG-ROM banks.
+    prg_banks: usize,
+    bus_conflicts: bool,
+}
+
+impl UxRom {
+    pub fn new(ines: &Ines, bus_conflicts: bool) -> Self {
+        Self {
+            bank: 0,
+            prg_banks: (ines.inesprg as usize).max(1),
+            bus_conflicts,
+        }
+    }
+}
+
+impl Mapper for UxRom {
+    fn write(&mut self, _address: u16, value: u8, _cycle: u64) {
+        self.bank = value;
+    }
+
+    fn prg_banks(&self) -> [usize; 4] {
+        let bank = self.bank as usize;
+        let last = self.prg_banks - 1;
+        [bank * 2, bank * 2 + 1, last * 2, last * 2 + 1]
+    }
+
+    fn chr_banks(&self) -> [usize; 8] {
+        [0, 1, 2, 3, 4, 5, 6, 7]
+    }
+
+    fn bus_conflicts(&self) -> bool {
+        self.bus_conflicts
+    }
+}
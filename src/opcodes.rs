@@ -1,5 +1,7 @@
+use crate::ExecutionErrorReason;
 use crate::MemoryTarget;
 use crate::State;
+use crate::interrupts;
 use log::debug;
 use shared::Opcode;
 use shared::flags;
@@ -10,13 +12,18 @@ use shared::flags;
 /// Shift operation, right indicates bitshift to the right, left otherwise.
 /// Rotate indicates if the old carry is placed as the newly shifted in bit.
 /// If addr is None, the shift will be done on the accumulator.
-fn shift(state: &mut State, addr: Option<u16>, right: bool, rotate: bool) {
+/// Returns the shifted value.
+fn shift(state: &mut State, addr: Option<u16>, right: bool, rotate: bool) -> u8 {
     // read value to be shifted
     let old = if let Some(addr) = addr {
         state.read(addr, false)
     } else {
         state.a
     };
+    // read-modify-write instructions write the old value back first
+    if let Some(addr) = addr {
+        state.write(addr, old);
+    }
     // find the bit that will be outshifted (new carry)
     let new_c = old & (1 << if right { 0 } else { 7 });
     // shift
@@ -35,79 +42,98 @@ fn shift(state: &mut State, addr: Option<u16>, right: bool, rotate: bool) {
     // set carry
     state.set_flag(flags::C, new_c != 0);
     new_value(state, val);
+    val
 }
 
-/// add: use addition, otherwise subtraction
+/// add: use addition, otherwise subtraction.
+/// Subtraction is addition of the inverted argument, the carry acting as "not borrow".
 fn addsub(state: &mut State, sub: bool, arg1: u8, arg2: u8) -> u8 {
-    // 'not' the carry if subtracting
-    let old_c = state.get_flag(flags::C) ^ sub;
-
-    // determine what operations to use to calculate unsigned carryover
-    // and signed carryover for addition/subtraction respectively
-    let op_u = if sub {
-        |x: u8, y: u8| x.overflowing_sub(y)
-    } else {
-        |x: u8, y: u8| x.overflowing_add(y)
-    };
-    let op_i = if sub {
-        |x: i8, y: i8| x.overflowing_sub(y)
-    } else {
-        |x: i8, y: i8| x.overflowing_add(y)
-    };
-
-    // Get c/carry (unsigned overflow) and the result
-    let (val, c) = op_u(arg1, arg2);
-    let (val, c_2) = op_u(val, old_c as u8);
-
-    // Get v/overflow (signed overflow)
-    let (val_test, v) = op_i(arg1 as i8, arg2 as i8);
-    let (_, v_2) = op_i(val_test, old_c as i8);
-
-    //state.a = val;
-    state.set_flag(flags::C, c | c_2);
-    state.set_flag(flags::V, v | v_2);
+    let arg2 = if sub { !arg2 } else { arg2 };
+    let sum = arg1 as u16 + arg2 as u16 + state.get_flag(flags::C) as u16;
+    let val = sum as u8;
+    state.set_flag(flags::C, sum > 0xFF);
+    // signed overflow: both arguments have the same sign, the result another
+    state.set_flag(flags::V, !(arg1 ^ arg2) & (arg1 ^ val) & 0x80 != 0);
     new_value(state, val);
     val
 }
 
-fn incdec(state: &mut State, addr: u16, inc: bool) {
+/// Compare instructions, subtraction without the carry going in or V coming out.
+fn compare(state: &mut State, reg: u8, arg: u8) {
+    let (val, borrow) = reg.overflowing_sub(arg);
+    state.set_flag(flags::C, !borrow);
+    new_value(state, val);
+}
+
+/// Returns the new value.
+fn incdec(state: &mut State, addr: u16, inc: bool) -> u8 {
     let val = state.read(addr, false);
-    state.write(
-        addr,
-        if inc {
-            val.wrapping_add(1)
-        } else {
-            val.wrapping_sub(1)
-        },
-    );
+    // the old value is written back first, like every read-modify-write
+    state.write(addr, val);
+    let val = if inc {
+        val.wrapping_add(1)
+    } else {
+        val.wrapping_sub(1)
+    };
+    state.write(addr, val);
     new_value(state, val);
+    val
 }
 
 fn store_register(state: &mut State, addr: u16, reg: u8) {
     state.write(addr, reg);
 }
 
+/// The unstable SHA/SHX/SHY/TAS stores: writes `val & (H + 1)` where H is the
+/// high byte of the address before indexing. When indexing crosses a page the
+/// high byte of the target address gets replaced by the stored value.
+fn store_high(state: &mut State, addr: u16, index: u8, val: u8) {
+    let base = addr.wrapping_sub(index as u16);
+    let val = val & ((base >> 8) as u8).wrapping_add(1);
+    let addr = if (base & 0xFF00) != (addr & 0xFF00) {
+        ((val as u16) << 8) | (addr & 0x00FF)
+    } else {
+        addr
+    };
+    state.write(addr, val);
+}
+
+/// A taken branch costs one extra cycle, two if it lands on another page.
+/// Those read the next opcode and the target before the carry into its page.
 fn branch(state: &mut State, addr: u16, cond: bool) {
+    let old = state.read(addr, false);
     if cond {
-        let old = state.read(addr, false);
         debug!("branching: {:?}", old as i8);
-        state.pc = state.pc.wrapping_add_signed((old as i8) as i16);
+        let new_pc = state.pc.wrapping_add_signed((old as i8) as i16);
+        state.read(state.pc, false);
+        if (new_pc & 0xFF00) != (state.pc & 0xFF00) {
+            state.read((state.pc & 0xFF00) | (new_pc & 0x00FF), false);
+            state.cycles += 2;
+        } else {
+            state.cycles += 1;
+        }
+        state.pc = new_pc;
+    } else {
+        debug!("not branching");
     }
-    debug!("not branching");
 }
 
 /// Expects pc to be at next instruction
-pub fn run(opcode: Opcode, state: &mut State, memory_target: MemoryTarget) {
+pub fn run(
+    opcode: Opcode,
+    state: &mut State,
+    memory_target: MemoryTarget,
+) -> Result<(), ExecutionErrorReason> {
     use crate::MemoryTarget::*;
     use Opcode::*;
     macro_rules! push {
         ($what:expr) => {{
-            state.write(state.sp as u16 + 0x0100, $what);
-            dec_stack(state);
+            push(state, $what);
         }};
     }
     macro_rules! pull {
         ($what:expr) => {{
+            stack_dummy_read(state);
             inc_stack(state);
             $what = state.read(state.sp as u16 + 0x0100, false);
         }};
@@ -123,7 +149,12 @@ pub fn run(opcode: Opcode, state: &mut State, memory_target: MemoryTarget) {
             }
             macro_rules! bitwise {
                 ($op:tt) => {{
-                    let val = state.a $op state.read(addr, false);
+                    let arg = state.read(addr, false);
+                    bitwise!($op, arg)
+                }};
+                ($op:tt, $arg:expr) => {{
+                    let arg = $arg;
+                    let val = state.a $op arg;
                     state.a = val;
                     new_value(state, val);
                 }};
@@ -143,22 +174,23 @@ pub fn run(opcode: Opcode, state: &mut State, memory_target: MemoryTarget) {
                 }
                 CMP => {
                     let arg2 = state.read(addr, false);
-                    addsub(state, true, state.a, arg2);
+                    compare(state, state.a, arg2);
                 }
                 CPX => {
                     let arg2 = state.read(addr, false);
-                    addsub(state, true, state.x, arg2);
+                    compare(state, state.x, arg2);
                 }
                 CPY => {
                     let arg2 = state.read(addr, false);
-                    addsub(state, true, state.y, arg2);
+                    compare(state, state.y, arg2);
                 }
 
                 // The weird BIT instruction.
                 // A AND M -> Z, M7 -> N, M6 -> V
                 BIT => {
                     let arg = state.read(addr, false);
-                    state.sr = arg & 0b11000000;
+                    state.set_flag(flags::N, arg & flags::N != 0);
+                    state.set_flag(flags::V, arg & flags::V != 0);
                     state.set_flag(flags::Z, state.a & arg == 0);
                 }
 
@@ -170,10 +202,18 @@ pub fn run(opcode: Opcode, state: &mut State, memory_target: MemoryTarget) {
                 EOR => bitwise!(^),
 
                 // Shift operations
-                ROL => shift(state, Some(addr), false, true),
-                ROR => shift(state, Some(addr), true, true),
-                ASL => shift(state, Some(addr), false, false),
-                LSR => shift(state, Some(addr), true, false),
+                ROL => {
+                    shift(state, Some(addr), false, true);
+                }
+                ROR => {
+                    shift(state, Some(addr), true, true);
+                }
+                ASL => {
+                    shift(state, Some(addr), false, false);
+                }
+                LSR => {
+                    shift(state, Some(addr), true, false);
+                }
 
                 // Jump
                 JMP => {
@@ -204,29 +244,137 @@ pub fn run(opcode: Opcode, state: &mut State, memory_target: MemoryTarget) {
                 STY => store_register(state, addr, state.y),
 
                 // Increments / Decrements
-                DEC => incdec(state, addr, false),
-                INC => incdec(state, addr, true),
+                DEC => {
+                    incdec(state, addr, false);
+                }
+                INC => {
+                    incdec(state, addr, true);
+                }
 
                 // Subroutines
-                JSR => {
-                    push_pc(state);
-                    state.pc = addr;
+                // The return address was pushed while fetching the target,
+                // it is the last byte of the JSR, RTS adds one
+                JSR => state.pc = addr,
+
+                // Dummy read
+                NOP => {
+                    state.read(addr, false);
+                }
+
+                // Illegal opcodes
+                // Read-modify-write, then operate on A
+                SLO => {
+                    let val = shift(state, Some(addr), false, false);
+                    bitwise!(|, val)
+                }
+                RLA => {
+                    let val = shift(state, Some(addr), false, true);
+                    bitwise!(&, val)
+                }
+                SRE => {
+                    let val = shift(state, Some(addr), true, false);
+                    bitwise!(^, val)
+                }
+                RRA => {
+                    let val = shift(state, Some(addr), true, true);
+                    state.a = addsub(state, false, state.a, val);
+                }
+                DCP => {
+                    let val = incdec(state, addr, false);
+                    compare(state, state.a, val);
+                }
+                ISC => {
+                    let val = incdec(state, addr, true);
+                    state.a = addsub(state, true, state.a, val);
+                }
+
+                // Combined loads and stores
+                LAX => {
+                    let val = state.read(addr, false);
+                    state.a = val;
+                    state.x = val;
+                    new_value(state, val);
+                }
+                SAX => store_register(state, addr, state.a & state.x),
+                LAS => {
+                    let val = state.read(addr, false) & state.sp;
+                    state.a = val;
+                    state.x = val;
+                    state.sp = val;
+                    new_value(state, val);
                 }
 
-                o => unimplemented!("address provided to opcode {o}, pc = {:#06X}", state.pc),
+                // Immediate combinations
+                ANC => {
+                    bitwise!(&);
+                    state.set_flag(flags::C, state.get_flag(flags::N));
+                }
+                ALR => {
+                    bitwise!(&);
+                    shift(state, None, true, false);
+                }
+                ARR => {
+                    let arg = state.read(addr, false);
+                    let val = ((state.a & arg) >> 1) | ((state.get_flag(flags::C) as u8) << 7);
+                    state.a = val;
+                    new_value(state, val);
+                    state.set_flag(flags::C, val & (1 << 6) != 0);
+                    state.set_flag(flags::V, ((val >> 6) ^ (val >> 5)) & 1 != 0);
+                }
+                SBX => {
+                    let arg = state.read(addr, false);
+                    let (val, borrow) = (state.a & state.x).overflowing_sub(arg);
+                    state.x = val;
+                    state.set_flag(flags::C, !borrow);
+                    new_value(state, val);
+                }
+                USB => {
+                    let arg2 = state.read(addr, false);
+                    state.a = addsub(state, true, state.a, arg2);
+                }
+
+                // Unstable, depend on the analog "magic" constant
+                ANE => {
+                    let arg = state.read(addr, false);
+                    let val = (state.a | state.magic) & state.x & arg;
+                    state.a = val;
+                    new_value(state, val);
+                }
+                LXA => {
+                    let arg = state.read(addr, false);
+                    let val = (state.a | state.magic) & arg;
+                    state.a = val;
+                    state.x = val;
+                    new_value(state, val);
+                }
+
+                // Unstable, stores anded with the high byte of the address + 1
+                SHA => store_high(state, addr, state.y, state.a & state.x),
+                SHX => store_high(state, addr, state.y, state.x),
+                SHY => store_high(state, addr, state.x, state.y),
+                TAS => {
+                    state.sp = state.a & state.x;
+                    store_high(state, addr, state.y, state.a & state.x);
+                }
+
+                _ => return Err(ExecutionErrorReason::Unimplemented),
             }
         }
         Accumulator => match opcode {
             // Shift Instructions
-            ROL => shift(state, None, false, true),
-            ROR => shift(state, None, true, true),
-            ASL => shift(state, None, false, false),
-            LSR => shift(state, None, true, false),
-            _ => unimplemented!(
-                "opcode {:?} not implemented for accumulator operation, pc = {:#06X}",
-                opcode,
-                state.pc
-            ),
+            ROL => {
+                shift(state, None, false, true);
+            }
+            ROR => {
+                shift(state, None, true, true);
+            }
+            ASL => {
+                shift(state, None, false, false);
+            }
+            LSR => {
+                shift(state, None, true, false);
+            }
+            _ => return Err(ExecutionErrorReason::Unimplemented),
         },
         Impl => {
             macro_rules! set_flag {
@@ -251,9 +399,15 @@ pub fn run(opcode: Opcode, state: &mut State, memory_target: MemoryTarget) {
             match opcode {
                 // Stack Instructions
                 PHA => push!(state.a),
-                PHP => push!(state.sr | (1 << 5) | flags::B),
-                PLA => pull!(state.a),
-                PLP => pull!(state.sr),
+                PHP => push!(state.sr | flags::U | flags::B),
+                PLA => {
+                    pull!(state.a);
+                    new_value(state, state.a);
+                }
+                PLP => {
+                    stack_dummy_read(state);
+                    pull_sr(state);
+                }
 
                 // Transfer Instructions
                 TAX => transfer!(state.a, state.x),
@@ -261,7 +415,8 @@ pub fn run(opcode: Opcode, state: &mut State, memory_target: MemoryTarget) {
                 TXA => transfer!(state.x, state.a),
                 TYA => transfer!(state.y, state.a),
                 TSX => transfer!(state.sp, state.x),
-                TXS => transfer!(state.x, state.sp),
+                // The only transfer that leaves the flags alone
+                TXS => state.sp = state.x,
 
                 // Flag instructions
                 CLC => set_flag!(flags::C, false),
@@ -280,13 +435,16 @@ pub fn run(opcode: Opcode, state: &mut State, memory_target: MemoryTarget) {
 
                 // Return from interrupt
                 RTI => {
-                    pull!(state.sr);
+                    stack_dummy_read(state);
+                    pull_sr(state);
                     pull_pc(state);
                 }
 
                 // Return from subroutine
                 RTS => {
+                    stack_dummy_read(state);
                     pull_pc(state);
+                    state.read(state.pc, false);
                     state.inc_pc();
                 }
 
@@ -294,20 +452,15 @@ pub fn run(opcode: Opcode, state: &mut State, memory_target: MemoryTarget) {
                 BRK => {
                     // Skipping byte representing the reason for the interrupt
                     state.inc_pc();
-                    push_pc(state);
-                    push!(state.sr | flags::B | flags::I);
-                    state.pc = state.read_u16(shared::vectors::BRK);
+                    interrupts::interrupt(state, shared::vectors::BRK, true);
                 }
 
                 NOP => {}
-                _ => unimplemented!(
-                    "implied opcode not implemented: {:?}, pc = {:#06X}",
-                    opcode,
-                    state.pc
-                ),
+                _ => return Err(ExecutionErrorReason::Unimplemented),
             }
         }
     }
+    Ok(())
 }
 
 fn inc_stack(state: &mut State) {
@@ -316,10 +469,15 @@ fn inc_stack(state: &mut State) {
 }
 
 fn dec_stack(state: &mut State) {
-    let (new_pos, _) = state.sp.overflowing_add(1);
+    let (new_pos, _) = state.sp.overflowing_sub(1);
     state.sp = new_pos;
 }
 
+/// Pulls start with a read of the stack before the pointer is increased.
+fn stack_dummy_read(state: &mut State) {
+    state.read(state.sp as u16 | 0x0100, false);
+}
+
 fn pull_pc(state: &mut State) {
     inc_stack(state);
     let pc_lo = state.read(state.sp as u16 | 0x0100, false);
@@ -328,7 +486,19 @@ fn pull_pc(state: &mut State) {
     state.pc = ((pc_hi as u16) << 8) | pc_lo as u16;
 }
 
-fn push_pc(state: &mut State) {
+pub fn push(state: &mut State, val: u8) {
+    state.write(state.sp as u16 | 0x0100, val);
+    dec_stack(state);
+}
+
+/// The B flag only exists on the stack, bit 5 always reads as set.
+fn pull_sr(state: &mut State) {
+    inc_stack(state);
+    let sr = state.read(state.sp as u16 | 0x0100, false);
+    state.sr = (sr & !flags::B) | flags::U;
+}
+
+pub fn push_pc(state: &mut State) {
     let pc_hi = (state.pc >> 8) as u8;
     state.write(state.sp as u16 | 0x0100, pc_hi);
     dec_stack(state);
@@ -0,0 +1,520 @@
+//! The PPU, stepped alongside the CPU and rendering one scanline at a time
+//! into an indexed framebuffer, https://www.nesdev.org/wiki/PPU_rendering
+use crate::State;
+
+/// Width of the framebuffer in pixels.
+pub const WIDTH: usize = 256;
+/// Height of the framebuffer in pixels.
+pub const HEIGHT: usize = 240;
+
+/// Dots per scanline.
+pub const DOTS: u16 = 341;
+/// Scanlines per frame, including vblank and the pre-render line.
+pub const SCANLINES: u16 = 262;
+/// The scanline on which vblank starts.
+pub const VBLANK_SCANLINE: u16 = 241;
+/// The last scanline, preparing the first visible one.
+pub const PRE_RENDER_SCANLINE: u16 = 261;
+
+/// PPUCTRL ($2000) bits.
+pub mod ctrl {
+    pub const NAMETABLE: u8 = 0b11;
+    pub const INCREMENT_32: u8 = 1 << 2;
+    pub const SPRITE_TABLE: u8 = 1 << 3;
+    pub const BACKGROUND_TABLE: u8 = 1 << 4;
+    pub const SPRITE_8X16: u8 = 1 << 5;
+    pub const NMI: u8 = 1 << 7;
+}
+
+/// PPUMASK ($2001) bits.
+pub mod mask {
+    pub const GRAYSCALE: u8 = 1 << 0;
+    pub const BACKGROUND_LEFT: u8 = 1 << 1;
+    pub const SPRITES_LEFT: u8 = 1 << 2;
+    pub const BACKGROUND: u8 = 1 << 3;
+    pub const SPRITES: u8 = 1 << 4;
+}
+
+/// How the four nametables at $2000-$2FFF map onto the 2KiB of CIRAM,
+/// https://www.nesdev.org/wiki/Mirroring#Nametable_Mirroring
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mirroring {
+    /// $2000 and $2400 share the first KiB, $2800 and $2C00 the second.
+    Horizontal,
+    /// $2000 and $2800 share the first KiB, $2400 and $2C00 the second.
+    Vertical,
+    /// All four show the first KiB.
+    SingleScreenLower,
+    /// All four show the second KiB.
+    SingleScreenUpper,
+    /// The cartridge adds 2KiB so every nametable is separate.
+    FourScreen,
+}
+
+impl Mirroring {
+    /// From `Ines::mirroring`, bit 0 set is vertical and bit 3 four-screen.
+    pub fn from_ines(mirroring: u16) -> Self {
+        if mirroring & 0b1000 != 0 {
+            Mirroring::FourScreen
+        } else if mirroring & 1 != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        }
+    }
+
+    /// Offset into `PpuState::vram` for an address in $2000-$3EFF.
+    pub fn vram_offset(self, address: u16) -> usize {
+        // $3000-$3EFF mirrors $2000-$2EFF
+        let address = (address & 0x0FFF) as usize;
+        let table = address / 0x400;
+        let page = match self {
+            Mirroring::Horizontal => table / 2,
+            Mirroring::Vertical => table % 2,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+            Mirroring::FourScreen => table,
+        };
+        page * 0x400 + (address & 0x3FF)
+    }
+}
+
+/// RGB values for the 64 colors of the 2C02.
+#[rustfmt::skip]
+pub const PALETTE: [[u8; 3]; 64] = [
+    [0x66, 0x66, 0x66], [0x00, 0x2A, 0x88], [0x14, 0x12, 0xA7], [0x3B, 0x00, 0xA4],
+    [0x5C, 0x00, 0x7E], [0x6E, 0x00, 0x40], [0x6C, 0x06, 0x00], [0x56, 0x1D, 0x00],
+    [0x33, 0x35, 0x00], [0x0B, 0x48, 0x00], [0x00, 0x52, 0x00], [0x00, 0x4F, 0x08],
+    [0x00, 0x40, 0x4D], [0x00, 0x00, 0x00], [0x00, 0x00, 0x00], [0x00, 0x00, 0x00],
+    [0xAD, 0xAD, 0xAD], [0x15, 0x5F, 0xD9], [0x42, 0x40, 0xFF], [0x75, 0x27, 0xFE],
+    [0xA0, 0x1A, 0xCC], [0xB7, 0x1E, 0x7B], [0xB5, 0x31, 0x20], [0x99, 0x4E, 0x00],
+    [0x6B, 0x6D, 0x00], [0x38, 0x87, 0x00], [0x0C, 0x93, 0x00], [0x00, 0x8F, 0x32],
+    [0x00, 0x7C, 0x8D], [0x00, 0x00, 0x00], [0x00, 0x00, 0x00], [0x00, 0x00, 0x00],
+    [0xFF, 0xFE, 0xFF], [0x64, 0xB0, 0xFF], [0x92, 0x90, 0xFF], [0xC6, 0x76, 0xFF],
+    [0xF3, 0x6A, 0xFF], [0xFE, 0x6E, 0xCC], [0xFE, 0x81, 0x70], [0xEA, 0x9E, 0x22],
+    [0xBC, 0xBE, 0x00], [0x88, 0xD8, 0x00], [0x5C, 0xE4, 0x30], [0x45, 0xE0, 0x82],
+    [0x48, 0xCD, 0xDE], [0x4F, 0x4F, 0x4F], [0x00, 0x00, 0x00], [0x00, 0x00, 0x00],
+    [0xFF, 0xFE, 0xFF], [0xC0, 0xDF, 0xFF], [0xD3, 0xD2, 0xFF], [0xE8, 0xC8, 0xFF],
+    [0xFB, 0xC2, 0xFF], [0xFE, 0xC4, 0xEA], [0xFE, 0xCC, 0xC5], [0xF7, 0xD8, 0xA5],
+    [0xE4, 0xE5, 0x94], [0xCF, 0xEF, 0x96], [0xBD, 0xF4, 0xAB], [0xB3, 0xF3, 0xCC],
+    [0xB5, 0xEB, 0xF2], [0xB8, 0xB8, 0xB8], [0x00, 0x00, 0x00], [0x00, 0x00, 0x00],
+];
+
+/// Converts the indexed framebuffer to RGBA, 4 bytes per pixel.
+pub fn to_rgba(framebuffer: &[u8]) -> Vec<u8> {
+    framebuffer
+        .iter()
+        .flat_map(|i| {
+            let [r, g, b] = PALETTE[(*i & 0x3F) as usize];
+            [r, g, b, 0xFF]
+        })
+        .collect()
+}
+
+/// Handles a CPU write to $2000-$2007 (mirrored up to $3FFF).
+pub fn write_register(state: &mut State, address: u16, value: u8) {
+    let ppu = &mut state.ppu_state;
+    match address & 0b111 {
+        // PPUCTRL
+        0 => {
+            let nmi_enabled = value & ctrl::NMI != 0 && ppu.ctrl & ctrl::NMI == 0;
+            ppu.ctrl = value;
+            ppu.t = (ppu.t & !0x0C00) | ((value & ctrl::NAMETABLE) as u16) << 10;
+            // Enabling NMI during vblank fires one right away
+            if nmi_enabled && ppu.vblank {
+                state.trigger_nmi();
+            }
+        }
+        // PPUMASK
+        1 => ppu.mask = value,
+        // OAMADDR
+        3 => ppu.oam_addr = value,
+        // OAMDATA
+        4 => {
+            ppu.oam[ppu.oam_addr as usize] = value;
+            ppu.oam_addr = ppu.oam_addr.wrapping_add(1);
+        }
+        // PPUSCROLL
+        5 => {
+            if !ppu.w {
+                ppu.t = (ppu.t & !0x001F) | (value >> 3) as u16;
+                ppu.x = value & 0b111;
+            } else {
+                ppu.t =
+                    (ppu.t & !0x73E0) | ((value & 0b111) as u16) << 12 | ((value >> 3) as u16) << 5;
+            }
+            ppu.w = !ppu.w;
+        }
+        // PPUADDR, high byte first
+        6 => {
+            if !ppu.w {
+                ppu.t = (ppu.t & 0x00FF) | ((value & 0x3F) as u16) << 8;
+            } else {
+                ppu.t = (ppu.t & 0xFF00) | value as u16;
+                ppu.v = ppu.t;
+            }
+            ppu.w = !ppu.w;
+        }
+        // PPUDATA
+        7 => {
+            let v = ppu.v;
+            increment_vram_address(state);
+            state.ppu_write(v & 0x3FFF, value);
+        }
+        // PPUSTATUS is read-only
+        _ => {}
+    }
+}
+
+/// Handles a CPU read from $2000-$2007, only PPUSTATUS, OAMDATA and PPUDATA
+/// can be read. If `read_only` is set nothing changes.
+pub fn read_register(state: &mut State, address: u16, read_only: bool) -> u8 {
+    let ppu = &mut state.ppu_state;
+    match address & 0b111 {
+        // PPUSTATUS
+        2 => {
+            let status = (ppu.vblank as u8) << 7
+                | (ppu.sprite_0_hit as u8) << 6
+                | (ppu.sprite_overflow as u8) << 5;
+            if !read_only {
+                ppu.vblank = false;
+                ppu.w = false;
+            }
+            status
+        }
+        // OAMDATA, the unimplemented attribute bits read as 0
+        4 => {
+            let value = ppu.oam[ppu.oam_addr as usize];
+            if ppu.oam_addr & 0b11 == 2 {
+                value & 0xE3
+            } else {
+                value
+            }
+        }
+        // PPUDATA, reads are delayed by a buffer except for the palettes
+        7 => {
+            let v = ppu.v & 0x3FFF;
+            let buffered = ppu.tmp_val;
+            if read_only {
+                return if v >= 0x3F00 {
+                    state.ppu_read(v, true)
+                } else {
+                    buffered
+                };
+            }
+            increment_vram_address(state);
+            if v >= 0x3F00 {
+                // The buffer gets the nametable byte "under" the palettes
+                state.ppu_state.tmp_val = state.ppu_read(v - 0x1000, false);
+                state.ppu_read(v, false)
+            } else {
+                state.ppu_state.tmp_val = state.ppu_read(v, false);
+                buffered
+            }
+        }
+        _ => 0,
+    }
+}
+
+/// A write to $4014 copies page `page` of the CPU bus to OAM, starting at
+/// OAMADDR. The CPU is stalled for 513 cycles, plus one when the write
+/// happened on an odd cycle.
+pub fn oam_dma(state: &mut State, page: u8) {
+    let stall = if state.cycles % 2 == 1 { 514 } else { 513 };
+    for i in 0..=0xFF {
+        let value = state.read((page as u16) << 8 | i, false);
+        let ppu = &mut state.ppu_state;
+        ppu.oam[ppu.oam_addr as usize] = value;
+        ppu.oam_addr = ppu.oam_addr.wrapping_add(1);
+    }
+    state.cycles += stall;
+}
+
+/// After a PPUDATA access v goes down a row or right a column.
+fn increment_vram_address(state: &mut State) {
+    let ppu = &mut state.ppu_state;
+    let increment = if ppu.ctrl & ctrl::INCREMENT_32 != 0 {
+        32
+    } else {
+        1
+    };
+    ppu.v = ppu.v.wrapping_add(increment) & 0x7FFF;
+}
+
+/// Moves v to the next tile, wrapping into the horizontally adjacent nametable.
+fn increment_x(v: &mut u16) {
+    if *v & 0x001F == 31 {
+        *v &= !0x001F;
+        *v ^= 0x0400;
+    } else {
+        *v += 1;
+    }
+}
+
+/// Moves v to the next row of pixels, wrapping into the vertically adjacent
+/// nametable after row 29.
+fn increment_y(v: &mut u16) {
+    if *v & 0x7000 != 0x7000 {
+        *v += 0x1000;
+        return;
+    }
+    *v &= !0x7000;
+    let mut coarse_y = (*v & 0x03E0) >> 5;
+    if coarse_y == 29 {
+        coarse_y = 0;
+        *v ^= 0x0800;
+    } else if coarse_y == 31 {
+        // Attribute rows, wraps without switching nametable
+        coarse_y = 0;
+    } else {
+        coarse_y += 1;
+    }
+    *v = (*v & !0x03E0) | coarse_y << 5;
+}
+
+/// Runs the PPU for the given number of CPU cycles, three dots each.
+pub fn step(state: &mut State, cpu_cycles: u64) {
+    for _ in 0..cpu_cycles * 3 {
+        tick(state);
+    }
+}
+
+fn rendering_enabled(state: &State) -> bool {
+    state.ppu_state.mask & (mask::BACKGROUND | mask::SPRITES) != 0
+}
+
+/// Advances one dot.
+fn tick(state: &mut State) {
+    let ppu = &mut state.ppu_state;
+    ppu.dot += 1;
+    ppu.dots += 1;
+    // The odd frames are one dot shorter when rendering
+    let skip = ppu.scanline == PRE_RENDER_SCANLINE
+        && ppu.dot == DOTS - 1
+        && ppu.odd_frame
+        && ppu.mask & (mask::BACKGROUND | mask::SPRITES) != 0;
+    if ppu.dot == DOTS || skip {
+        ppu.dot = 0;
+        ppu.scanline += 1;
+        if ppu.scanline == SCANLINES {
+            ppu.scanline = 0;
+            ppu.odd_frame = !ppu.odd_frame;
+        }
+    }
+    let rendering = ppu.mask & (mask::BACKGROUND | mask::SPRITES) != 0;
+    match (ppu.scanline, ppu.dot) {
+        (0..=239, 256) => {
+            render_scanline(state);
+            if rendering {
+                increment_y(&mut state.ppu_state.v);
+            }
+        }
+        (PRE_RENDER_SCANLINE, 256) if rendering => {
+            increment_y(&mut ppu.v);
+            dummy_fetches(state);
+        }
+        // Back to the left edge
+        (0..=239 | PRE_RENDER_SCANLINE, 257) if rendering => {
+            ppu.v = (ppu.v & !0x041F) | (ppu.t & 0x041F);
+        }
+        // Back to the top
+        (PRE_RENDER_SCANLINE, 280) if rendering => {
+            ppu.v = (ppu.v & !0x7BE0) | (ppu.t & 0x7BE0);
+        }
+        (VBLANK_SCANLINE, 1) => {
+            ppu.vblank = true;
+            ppu.frame += 1;
+            if ppu.ctrl & ctrl::NMI != 0 {
+                state.trigger_nmi();
+            }
+        }
+        (PRE_RENDER_SCANLINE, 1) => {
+            ppu.vblank = false;
+            ppu.sprite_0_hit = false;
+            ppu.sprite_overflow = false;
+        }
+        _ => {}
+    }
+}
+
+/// The pattern fetches of the pre-render line, nothing is drawn but
+/// mappers watching the PPU bus see them.
+fn dummy_fetches(state: &mut State) {
+    let ctrl = state.ppu_state.ctrl;
+    let sprite_table = if ctrl & (ctrl::SPRITE_8X16 | ctrl::SPRITE_TABLE) != 0 {
+        0x1000
+    } else {
+        0x0000
+    };
+    for _ in 0..8 {
+        pattern_row(state, sprite_table, 0xFF, 0);
+    }
+    // The first two tiles of the next line
+    let background_table = if ctrl & ctrl::BACKGROUND_TABLE != 0 {
+        0x1000
+    } else {
+        0x0000
+    };
+    for _ in 0..2 {
+        pattern_row(state, background_table, 0, 0);
+    }
+}
+
+/// The two bit planes of one row of a tile.
+fn pattern_row(state: &mut State, table: u16, tile: u8, row: u16) -> (u8, u8) {
+    let address = table + tile as u16 * 16 + row;
+    (
+        state.ppu_read(address, false),
+        state.ppu_read(address + 8, false),
+    )
+}
+
+/// The 2 bit color of pixel `x` (0 is leftmost) in a row of a tile.
+fn pattern_pixel((lo, hi): (u8, u8), x: usize) -> u8 {
+    let bit = 7 - x;
+    ((lo >> bit) & 1) | (((hi >> bit) & 1) << 1)
+}
+
+/// A sprite pixel, before it is mixed with the background.
+#[derive(Clone, Copy)]
+struct SpritePixel {
+    /// Palette (4-7) and color, 0 if transparent.
+    color: u8,
+    behind_background: bool,
+    sprite_0: bool,
+}
+
+/// Fetches and mixes the background and sprites of the current scanline.
+fn render_scanline(state: &mut State) {
+    let y = state.ppu_state.scanline as usize;
+    let ctrl = state.ppu_state.ctrl;
+    let mask = state.ppu_state.mask;
+    if !rendering_enabled(state) {
+        let backdrop = state.ppu_read(0x3F00, true) & 0x3F;
+        state.ppu_state.framebuffer[y * WIDTH..(y + 1) * WIDTH].fill(backdrop);
+        return;
+    }
+
+    // Background, palette and color per pixel, 0 if transparent
+    let mut background = [0u8; WIDTH];
+    if mask & mask::BACKGROUND != 0 {
+        let table = if ctrl & ctrl::BACKGROUND_TABLE != 0 {
+            0x1000
+        } else {
+            0x0000
+        };
+        // The scroll position comes from v and fine x, 33 tiles cover
+        // the line when it is not aligned to a tile
+        let mut v = state.ppu_state.v;
+        let fine_x = state.ppu_state.x as usize;
+        for tile_index in 0..WIDTH / 8 + 1 {
+            let tile = state.ppu_read(0x2000 | (v & 0x0FFF), false);
+            let attribute = state.ppu_read(
+                0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07),
+                false,
+            );
+            let shift = ((v >> 4) & 4) | (v & 2);
+            let palette = ((attribute >> shift) & 0b11) << 2;
+            let row = pattern_row(state, table, tile, v >> 12);
+            for x in 0..8 {
+                let color = pattern_pixel(row, x);
+                let screen_x = (tile_index * 8 + x).wrapping_sub(fine_x);
+                if color != 0 && screen_x < WIDTH {
+                    background[screen_x] = palette | color;
+                }
+            }
+            increment_x(&mut v);
+        }
+    }
+
+    // Sprite evaluation, the sprites on this line in OAM order
+    let height = if ctrl & ctrl::SPRITE_8X16 != 0 { 16 } else { 8 };
+    let mut found = Vec::with_capacity(8);
+    for n in 0..64 {
+        let sprite_y = state.ppu_state.oam[n * 4] as usize;
+        // Sprites are drawn one line below their Y coordinate
+        if (sprite_y + 1..sprite_y + 1 + height).contains(&y) {
+            if found.len() == 8 {
+                state.ppu_state.sprite_overflow = true;
+                break;
+            }
+            found.push(n);
+        }
+    }
+
+    // Sprite fetches, always eight of them like the hardware
+    let mut sprites: [Option<SpritePixel>; WIDTH] = [None; WIDTH];
+    for slot in 0..8 {
+        let Some(&n) = found.get(slot) else {
+            let table = if height == 16 || ctrl & ctrl::SPRITE_TABLE != 0 {
+                0x1000
+            } else {
+                0x0000
+            };
+            pattern_row(state, table, 0xFF, 0);
+            continue;
+        };
+        let oam = &state.ppu_state.oam[n * 4..n * 4 + 4];
+        let (sprite_y, tile, attributes, sprite_x) =
+            (oam[0] as usize, oam[1], oam[2], oam[3] as usize);
+        let mut row = (y - sprite_y - 1) as u16;
+        if attributes & 0x80 != 0 {
+            row = height as u16 - 1 - row;
+        }
+        let (table, tile) = if height == 16 {
+            ((tile as u16 & 1) << 12, (tile & 0xFE) + (row / 8) as u8)
+        } else if ctrl & ctrl::SPRITE_TABLE != 0 {
+            (0x1000, tile)
+        } else {
+            (0x0000, tile)
+        };
+        let pattern = pattern_row(state, table, tile, row % 8);
+        let palette = 0b100 | (attributes & 0b11);
+        for x in 0..8 {
+            let screen_x = sprite_x + x;
+            if screen_x >= WIDTH || sprites[screen_x].is_some() {
+                continue;
+            }
+            let color = pattern_pixel(pattern, if attributes & 0x40 != 0 { 7 - x } else { x });
+            if color != 0 {
+                sprites[screen_x] = Some(SpritePixel {
+                    color: palette << 2 | color,
+                    behind_background: attributes & 0x20 != 0,
+                    sprite_0: n == 0,
+                });
+            }
+        }
+    }
+
+    // Mixing
+    for x in 0..WIDTH {
+        let bg = if mask & mask::BACKGROUND != 0 && (x >= 8 || mask & mask::BACKGROUND_LEFT != 0) {
+            background[x]
+        } else {
+            0
+        };
+        let sprite = if mask & mask::SPRITES != 0 && (x >= 8 || mask & mask::SPRITES_LEFT != 0) {
+            sprites[x]
+        } else {
+            None
+        };
+        let palette_index = match sprite {
+            Some(sprite) => {
+                if sprite.sprite_0 && bg != 0 && x != 255 {
+                    state.ppu_state.sprite_0_hit = true;
+                }
+                if sprite.behind_background && bg != 0 {
+                    bg
+                } else {
+                    sprite.color
+                }
+            }
+            None => bg,
+        };
+        let mut color = state.ppu_read(0x3F00 | palette_index as u16, true) & 0x3F;
+        if mask & mask::GRAYSCALE != 0 {
+            color &= 0x30;
+        }
+        state.ppu_state.framebuffer[y * WIDTH + x] = color;
+    }
+}
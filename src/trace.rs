@@ -0,0 +1,57 @@
+//! CPU trace in the nestest/Nintendulator log format, one line per instruction:
+//! `C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7`
+//! PPU is the scanline and dot, counted from power-up like CYC, so after the
+//! 7 cycles of reset the PPU is 21 dots into the first scanline.
+use crate::State;
+use asmnes::Instruction;
+use shared::CODEPOINTS;
+use shared::Opcode::*;
+
+/// Formats the instruction at PC together with the registers, before it runs.
+/// Reads are done without side effects.
+pub fn trace_line(state: &mut State) -> String {
+    let opcode = state.read(state.pc, true);
+    let len = CODEPOINTS[opcode as usize].addressing_mode.get_len();
+    let bytes: Vec<u8> = (0..len)
+        .map(|i| state.read(state.pc.wrapping_add(i), true))
+        .collect();
+    let (disassembly, official) = match Instruction::from_bytes(&bytes) {
+        Some((instruction, _)) => {
+            let official = !is_unofficial(opcode);
+            (format!("{instruction}"), official)
+        }
+        None => (String::from("???"), false),
+    };
+    let bytes = bytes
+        .iter()
+        .map(|b| format!("{b:02X}"))
+        .collect::<Vec<String>>()
+        .join(" ");
+    format!(
+        "{:04X}  {:<8} {}{:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+        state.pc,
+        bytes,
+        if official { ' ' } else { '*' },
+        disassembly,
+        state.a,
+        state.x,
+        state.y,
+        state.sr,
+        state.sp,
+        state.ppu_state.scanline,
+        state.ppu_state.dot,
+        state.cycles
+    )
+}
+
+/// Unofficial opcodes are marked with a `*` in the log, this includes the
+/// alternative encodings of official instructions.
+fn is_unofficial(opcode: u8) -> bool {
+    let codepoint = &CODEPOINTS[opcode as usize];
+    match codepoint.opcode {
+        ALR | ANC | ANE | ARR | DCP | ISC | LAS | LAX | LXA | RLA | RRA | SAX | SBX | SHA | SHX
+        | SHY | SLO | SRE | TAS | USB | JAM => true,
+        NOP => opcode != 0xEA,
+        _ => false,
+    }
+}
@@ -0,0 +1,109 @@
+mod common;
+
+#[cfg(test)]
+mod test_apu {
+    use asmnes::AsmnesError;
+    use remun::State;
+
+    use crate::common;
+
+    /// Runs `program`, which ends in an endless loop, for `frames` frames.
+    fn run_frames(program: &str, frames: u64) -> Result<State, AsmnesError> {
+        let mut state = common::load(program)?;
+        state.run_frames(frames).unwrap();
+        Ok(state)
+    }
+
+    #[test]
+    fn test_length_counter_expires() -> Result<(), AsmnesError> {
+        // pulse 1 with length index 0, 10 half frames
+        let program = "LDA #$01\nSTA $4015\nLDA #$00\nSTA $4003\nJMP $C00A\n";
+        let mut state = run_frames(program, 1)?;
+        assert_eq!(state.read(0x4015, false) & 0x01, 0x01);
+        state.run_frames(5).unwrap();
+        assert_eq!(state.read(0x4015, false) & 0x01, 0x00);
+        Ok(())
+    }
+
+    #[test]
+    fn test_frame_counter_irq() -> Result<(), AsmnesError> {
+        let mut state = run_frames("LDA #$00\nSTA $4017\nJMP $C005\n", 2)?;
+        assert!(state.irq_asserted());
+        assert_eq!(state.read(0x4015, true) & 0x40, 0x40);
+        // reading acknowledges it
+        state.read(0x4015, false);
+        assert!(!state.irq_asserted());
+        // and inhibiting keeps it off
+        let state = run_frames("LDA #$40\nSTA $4017\nJMP $C005\n", 2)?;
+        assert!(!state.irq_asserted());
+        Ok(())
+    }
+
+    #[test]
+    fn test_dmc_irq() -> Result<(), AsmnesError> {
+        // a one byte sample from $C000 with the IRQ enabled
+        let program = "LDA #$80\nSTA $4010\nLDA #$00\nSTA $4013\nLDA #$10\nSTA $4015\nJMP $C00F\n";
+        let mut state = run_frames(program, 1)?;
+        assert!(state.irq_asserted());
+        assert_eq!(state.read(0x4015, true) & 0x90, 0x80);
+        Ok(())
+    }
+
+    #[test]
+    fn test_pulse_produces_samples() -> Result<(), AsmnesError> {
+        // 50% duty at constant volume 15, period $0FD (about 440Hz)
+        let program = "LDA #$01
+STA $4015
+LDA #$BF
+STA $4000
+LDA #$FD
+STA $4002
+LDA #$00
+STA $4003
+JMP $C014
+";
+        let mut state = run_frames(program, 1)?;
+        state.take_samples();
+        state.apu.sample_rate = 48_000;
+        state.run_frames(1).unwrap();
+        let samples = state.take_samples();
+        // 48000 / 60.1 frames per second
+        assert!((795..=802).contains(&samples.len()), "{}", samples.len());
+        let peak = samples.iter().fold(0.0f32, |a, s| a.max(s.abs()));
+        assert!(peak > 0.05, "{peak}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_channel_samples() -> Result<(), AsmnesError> {
+        let program = "LDA #$01
+STA $4015
+LDA #$BF
+STA $4000
+LDA #$FD
+STA $4002
+LDA #$00
+STA $4003
+JMP $C014
+";
+        let mut state = run_frames(program, 1)?;
+        state.apu.record_channels = true;
+        // the silent triangle holds a DC level until the filter removes it
+        state.run_frames(1).unwrap();
+        state.take_samples();
+        state.apu.take_channel_samples();
+        state.run_frames(1).unwrap();
+        let mixed = state.take_samples();
+        let [pulse1, pulse2, triangle, noise, dmc] = state.apu.take_channel_samples();
+        assert_eq!(pulse1.len(), mixed.len());
+        // only pulse 1 plays, so its stem is the mix
+        for (a, b) in pulse1.iter().zip(mixed.iter()) {
+            assert!((a - b).abs() < 1e-3, "{a} {b}");
+        }
+        for silent in [pulse2, triangle, noise, dmc] {
+            assert_eq!(silent.len(), mixed.len());
+            assert!(silent.iter().all(|s| s.abs() < 1e-3));
+        }
+        Ok(())
+    }
+}
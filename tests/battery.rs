@@ -0,0 +1,106 @@
+mod common;
+
+#[cfg(test)]
+mod test_battery {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use asmnes::AsmnesError;
+    use remun::State;
+
+    use crate::common;
+
+    /// MMC1 with battery-backed PRG-RAM, reset vector pointing at $C000.
+    const HEADER: &str = "
+.inesmap 1
+.inesmir 3
+.inesprg 1
+.ineschr 1
+.bank 1
+.org $FFFC
+.db $00
+.db $C0
+.bank 0
+.org $C000
+";
+
+    /// Assembles `program` as if it came from `source`.
+    fn assemble(program: &str, source: Option<PathBuf>) -> Result<shared::Ines, AsmnesError> {
+        let mut ines = common::assemble(&format!("{HEADER}{program}"))?;
+        ines.metadata.as_mut().unwrap().data_source = source;
+        Ok(ines)
+    }
+
+    #[test]
+    fn test_prg_ram() -> Result<(), AsmnesError> {
+        let program = "LDA #$2A\nSTA $6000\nLDX $6000\n";
+        let mut state = State::new(assemble(program, None)?).unwrap();
+        state.run_instructions(3).unwrap();
+        assert_eq!(state.x, 0x2A);
+        assert_eq!(state.export_save()[0], 0x2A);
+        // MMC1 can disable it
+        for bit in [0, 0, 0, 0, 1] {
+            state.cycles += 2;
+            state.write(0xE000, bit);
+        }
+        assert_eq!(state.read(0x6000, true), 0);
+        state.write(0x6000, 0x11);
+        assert_eq!(state.export_save()[0], 0x2A);
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_export() -> Result<(), AsmnesError> {
+        let mut state = State::new(assemble("", None)?).unwrap();
+        let mut save = vec![0; 0x2000];
+        save[0x1FFF] = 0x55;
+        state.import_save(&save).unwrap();
+        assert_eq!(state.read(0x7FFF, true), 0x55);
+        assert_eq!(state.export_save(), &save[..]);
+        assert!(state.import_save(&save[..0x1000]).is_err());
+        // nowhere to save to without a source file
+        assert_eq!(state.save_path(), None);
+        state.write_save().unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn test_sav_file() -> Result<(), AsmnesError> {
+        let dir = std::env::temp_dir().join(format!("remun-battery-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("game.asm");
+        let sav = dir.join("game.sav");
+        let program = "LDA #$2A\nSTA $7123\n";
+        let mut state = State::new(assemble(program, Some(source.clone()))?).unwrap();
+        assert_eq!(state.save_path(), Some(sav.clone()));
+        state.run_instructions(2).unwrap();
+        state.write_save().unwrap();
+        assert_eq!(fs::read(&sav).unwrap()[0x1123], 0x2A);
+        // loaded again on the next power-up
+        let mut state = State::new(assemble("", Some(source))?).unwrap();
+        assert_eq!(state.read(0x7123, true), 0x2A);
+        fs::remove_dir_all(dir).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn test_prg_ram_from_header() -> Result<(), AsmnesError> {
+        let mut ines = assemble("", None)?;
+        ines.battery = false;
+        ines.prg_nvram_size = 0;
+        // 2KiB mirrored through $6000-$7FFF, with a trainer at $7000
+        ines.prg_ram_size = 0x800;
+        ines.trainer = Some(vec![0xAA; 512]);
+        let mut state = State::new(ines.clone()).unwrap();
+        state.write(0x6001, 0x42);
+        assert_eq!(state.read(0x6801, true), 0x42);
+        assert_eq!(state.read(0x7000, true), 0xAA);
+        // none at all
+        ines.prg_ram_size = 0;
+        ines.trainer = None;
+        let mut state = State::new(ines).unwrap();
+        state.write(0x6000, 0x42);
+        assert_eq!(state.read(0x6000, true), 0);
+        Ok(())
+    }
+}
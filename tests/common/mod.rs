@@ -0,0 +1,62 @@
+//! Helpers shared by the integration tests and benchmarks, included with
+//! `mod common;` since every file in `tests/` is its own crate.
+#![allow(dead_code)]
+
+use asmnes::AsmnesError;
+use asmnes::lexer::lex;
+use asmnes::parser::parse;
+use remun::State;
+use shared::Ines;
+use shared::flags;
+
+/// NROM-128 with the reset vector pointing at $C000, where the program starts.
+pub const HEADER: &str = "
+.inesmap 0
+.inesmir 1
+.inesprg 1
+.ineschr 1
+.bank 1
+.org $FFFC
+.db $00
+.db $C0
+.bank 0
+.org $C000
+";
+
+/// Assembles a complete source, header included.
+pub fn assemble(source: &str) -> Result<Ines, AsmnesError> {
+    asmnes::logical_assemble(&parse(lex(source)?)?)
+}
+
+/// Powers up `program` after `HEADER`, without running it.
+pub fn load(program: &str) -> Result<State, AsmnesError> {
+    Ok(State::new(assemble(&format!("{HEADER}{program}"))?).unwrap())
+}
+
+/// A bare CPU on flat 64KiB RAM with `bytes` at `pc`, where PC points.
+/// The stack pointer and status are as after reset, I is set.
+pub fn flat_ram(pc: u16, bytes: &[u8]) -> State {
+    let mut state = State::new_flat_ram();
+    for (i, byte) in bytes.iter().enumerate() {
+        state.write(pc.wrapping_add(i as u16), *byte);
+    }
+    state.pc = pc;
+    state.sp = 0xFD;
+    state.sr = flags::U | flags::I;
+    state
+}
+
+/// Runs the instruction in `bytes` at `pc` on `flat_ram`, after `setup`.
+pub fn step(pc: u16, bytes: &[u8], setup: impl FnOnce(&mut State)) -> State {
+    let mut state = flat_ram(pc, bytes);
+    setup(&mut state);
+    state.run_one_instruction().unwrap();
+    state
+}
+
+/// Helper to run a simple program.
+pub fn run_program(n_instructions: u64, program: &str) -> Result<State, AsmnesError> {
+    let mut state = load(program)?;
+    state.run_instructions(n_instructions).unwrap();
+    Ok(state)
+}
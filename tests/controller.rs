@@ -0,0 +1,52 @@
+mod common;
+
+#[cfg(test)]
+mod test_controller {
+    use asmnes::AsmnesError;
+    use remun::State;
+    use remun::controller::Port;
+    use remun::controller::buttons;
+
+    use crate::common;
+
+    /// Strobes, then stores 9 reads of port 1 at $10-$18 and one of port 2 at $19.
+    fn read_controllers_program() -> String {
+        let mut program = String::from("LDA #$01\nSTA $4016\nLDA #$00\nSTA $4016\n");
+        for address in 0x10..=0x18 {
+            program += &format!("LDA $4016\nSTA ${address:02X}\n");
+        }
+        program + "LDA $4017\nSTA $19\n"
+    }
+
+    #[test]
+    fn test_buttons_shift_out_in_order() -> Result<(), AsmnesError> {
+        let program = read_controllers_program();
+        let mut state = common::load(&program)?;
+        state.set_buttons(Port::One, buttons::A | buttons::START | buttons::RIGHT);
+        state.set_button(Port::One, buttons::UP, true);
+        state.set_button(Port::One, buttons::UP, false);
+        state
+            .run_instructions(program.lines().count() as u64)
+            .unwrap();
+        let reads: Vec<u8> = (0x10..=0x19).map(|a| state.read(a, true)).collect();
+        assert_eq!(
+            reads,
+            [0x41, 0x40, 0x40, 0x41, 0x40, 0x40, 0x40, 0x41, 0x41, 0x40]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_strobe_held_reads_a() {
+        let mut state = State::new_flat_ram();
+        state.set_buttons(Port::Two, buttons::A);
+        let controller = &mut state.controllers[Port::Two.index()];
+        controller.write_strobe(true);
+        assert_eq!(controller.read(false), 1);
+        assert_eq!(controller.read(false), 1);
+        controller.write_strobe(false);
+        assert_eq!(controller.read(false), 1);
+        assert_eq!(controller.read(true), 0);
+        assert_eq!(controller.read(false), 0);
+    }
+}
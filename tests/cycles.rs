@@ -0,0 +1,53 @@
+mod common;
+
+#[cfg(test)]
+mod test_cycles {
+    use crate::common::step;
+    use remun::State;
+    use shared::flags;
+
+    /// Cycles taken by the instruction in `bytes` at `pc`, after `setup`.
+    fn cycles(pc: u16, bytes: &[u8], setup: impl FnOnce(&mut State)) -> u64 {
+        step(pc, bytes, setup).cycles
+    }
+
+    #[test]
+    fn test_indexed_reads_crossing_a_page() {
+        // LDA $10F0,X
+        assert_eq!(cycles(0x0200, &[0xBD, 0xF0, 0x10], |s| s.x = 0x0F), 4);
+        assert_eq!(cycles(0x0200, &[0xBD, 0xF0, 0x10], |s| s.x = 0x10), 5);
+        // LDX $10F0,Y
+        assert_eq!(cycles(0x0200, &[0xBE, 0xF0, 0x10], |s| s.y = 0x0F), 4);
+        assert_eq!(cycles(0x0200, &[0xBE, 0xF0, 0x10], |s| s.y = 0x10), 5);
+        // LDA ($20),Y with $20 pointing at $10F0
+        let pointer = |s: &mut State, y| {
+            s.write(0x0020, 0xF0);
+            s.write(0x0021, 0x10);
+            s.y = y;
+        };
+        assert_eq!(cycles(0x0200, &[0xB1, 0x20], |s| pointer(s, 0x0F)), 5);
+        assert_eq!(cycles(0x0200, &[0xB1, 0x20], |s| pointer(s, 0x10)), 6);
+    }
+
+    #[test]
+    fn test_indexed_writes_always_take_the_extra_cycle() {
+        // STA $10F0,X and INC $10F0,X
+        assert_eq!(cycles(0x0200, &[0x9D, 0xF0, 0x10], |s| s.x = 0x0F), 5);
+        assert_eq!(cycles(0x0200, &[0x9D, 0xF0, 0x10], |s| s.x = 0x10), 5);
+        assert_eq!(cycles(0x0200, &[0xFE, 0xF0, 0x10], |s| s.x = 0x0F), 7);
+        assert_eq!(cycles(0x0200, &[0xFE, 0xF0, 0x10], |s| s.x = 0x10), 7);
+    }
+
+    #[test]
+    fn test_branches() {
+        let zero = |s: &mut State| s.set_flag(flags::Z, true);
+        // BNE +$10, not taken and taken
+        assert_eq!(cycles(0x0200, &[0xD0, 0x10], zero), 2);
+        assert_eq!(cycles(0x0200, &[0xD0, 0x10], |_| {}), 3);
+        // from $02FF to $0300, and backwards from $0302 to $02FF
+        assert_eq!(cycles(0x02FD, &[0xD0, 0x01], |_| {}), 4);
+        assert_eq!(cycles(0x0300, &[0xD0, 0xFD], |_| {}), 4);
+        // BEQ -2 onto itself stays on the page
+        assert_eq!(cycles(0x0200, &[0xF0, 0xFE], zero), 3);
+    }
+}
@@ -0,0 +1,61 @@
+mod common;
+
+#[cfg(test)]
+mod test_devices {
+    use asmnes::AsmnesError;
+    use remun::AddressSpace;
+    use remun::BusDevice;
+
+    use crate::common;
+
+    /// Test-result mailbox, remembers the last write and counts cycles.
+    struct Mailbox {
+        result: u8,
+        reads: u8,
+        cycles: u64,
+    }
+
+    impl BusDevice for Mailbox {
+        fn read(&mut self, offset: u16) -> u8 {
+            self.reads += 1;
+            self.peek(offset)
+        }
+        fn write(&mut self, offset: u16, value: u8) {
+            if offset == 0 {
+                self.result = value;
+            }
+        }
+        fn peek(&self, offset: u16) -> u8 {
+            match offset {
+                0 => self.result,
+                1 => self.reads,
+                2 => self.cycles as u8,
+                _ => 0,
+            }
+        }
+        fn tick(&mut self, cycles: u64) {
+            self.cycles += cycles;
+        }
+    }
+
+    #[test]
+    fn test_custom_device() -> Result<(), AsmnesError> {
+        let program = "LDA #$2A\nSTA $6000\nLDX $6001\nLDY $6001\n";
+        let mut state = common::load(program)?;
+        let mailbox = Mailbox {
+            result: 0,
+            reads: 0,
+            cycles: 0,
+        };
+        let maps = state.memory().len();
+        state.map_device(AddressSpace::Cpu, 0x6000..=0x7FFF, Box::new(mailbox));
+        assert_eq!(state.memory().len(), maps + 1);
+        state.run_instructions(4).unwrap();
+        assert_eq!(state.read(0x6000, true), 0x2A);
+        // every load counts itself, peeking does not count
+        assert_eq!((state.x, state.y), (1, 2));
+        assert_eq!(state.read(0x6001, true), 2);
+        assert_eq!(state.read(0x6002, true), 2 + 4 + 4 + 4);
+        Ok(())
+    }
+}
@@ -1,21 +1,44 @@
+mod common;
+
 #[cfg(test)]
 mod test_general {
     use asmnes::AsmnesError;
-    use asmnes::AsmnesOutput;
-    use remun::State;
-    /// Helper to run a simple program.
-    fn run_program(n_instructions: u64, program: &str) -> Result<State, AsmnesError> {
-        let AsmnesOutput { banks, labels: _ } = asmnes::assemble(program)?;
-        let mut state = State::new_nrom128(banks[0].data.clone(), banks[1].data.clone());
-        state.run_instructions(n_instructions);
-        Ok(state)
+
+    use crate::common::run_program;
+
+    #[test]
+    fn test_transfer_registers() -> Result<(), AsmnesError> {
+        let state = run_program(3, "LDX #$02\nTXA\nTAY\n")?;
+        assert_eq!(state.a, 0x02);
+        assert_eq!(state.y, 0x02);
+        Ok(())
+    }
+
+    #[test]
+    fn test_subroutine_returns_after_jsr() -> Result<(), AsmnesError> {
+        // JSR to the RTS at $C006, which returns to the LDX at $C003
+        let state = run_program(3, "JSR $C006\nLDX #$01\nNOP\nRTS\n")?;
+        assert_eq!(state.pc, 0xC005);
+        assert_eq!(state.x, 0x01);
+        assert_eq!(state.sp, 0xFD);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sbc_and_cmp_carry() -> Result<(), AsmnesError> {
+        let state = run_program(3, "SEC\nLDA #$05\nSBC #$03\n")?;
+        assert_eq!(state.a, 0x02);
+        assert!(state.get_flag(shared::flags::C));
+        let state = run_program(2, "LDA #$03\nCMP #$05\n")?;
+        assert!(!state.get_flag(shared::flags::C));
+        assert!(state.get_flag(shared::flags::N));
+        Ok(())
     }
 
     #[test]
-    fn test_transfer_registers_by_label() -> Result<(), AsmnesError> {
-        //state.print_state();
-        //assert!(state.x == 0x02);
-        //assert!(state.y == 0x04);
+    fn test_zero_page_indexing_wraps() -> Result<(), AsmnesError> {
+        let state = run_program(4, "LDA #$42\nSTA $01\nLDX #$02\nLDY $FF,X\n")?;
+        assert_eq!(state.y, 0x42);
         Ok(())
     }
 }
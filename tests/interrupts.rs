@@ -0,0 +1,180 @@
+mod common;
+
+#[cfg(test)]
+mod test_interrupts {
+    use crate::common::flat_ram;
+    use remun::State;
+    use remun::interrupts::IrqSource;
+    use shared::flags;
+    use shared::vectors;
+
+    /// Flat RAM with NOPs at $0200, the NMI handler at $3000 and the IRQ/BRK
+    /// handler at $4000. The stack pointer is at $FD and I is clear.
+    fn idle_cpu() -> State {
+        let mut state = flat_ram(0x0200, &[0xEA; 0x10]);
+        for (vector, handler) in [(vectors::NMI, 0x3000u16), (vectors::IRQ, 0x4000)] {
+            let [lo, hi] = handler.to_le_bytes();
+            state.write(vector, lo);
+            state.write(vector + 1, hi);
+        }
+        state.sr = flags::U;
+        state
+    }
+
+    /// The status register pushed by the last interrupt.
+    fn pushed_status(state: &mut State) -> u8 {
+        state.read(0x0100 | state.sp.wrapping_add(1) as u16, true)
+    }
+
+    /// The return address pushed by the last interrupt.
+    fn pushed_pc(state: &mut State) -> u16 {
+        let lo = state.read(0x0100 | state.sp.wrapping_add(2) as u16, true);
+        let hi = state.read(0x0100 | state.sp.wrapping_add(3) as u16, true);
+        u16::from_le_bytes([lo, hi])
+    }
+
+    #[test]
+    fn test_irq_masked_by_i() {
+        let mut state = idle_cpu();
+        state.sr |= flags::I;
+        // CLI at $0201
+        state.write(0x0201, 0x58);
+        state.set_irq(IrqSource::External, true);
+        state.run_one_instruction().unwrap();
+        assert_eq!(state.pc, 0x0201);
+        state.run_one_instruction().unwrap();
+        assert_eq!(state.pc, 0x0202);
+        // the instruction after CLI still runs, then the held line is serviced
+        state.run_one_instruction().unwrap();
+        assert_eq!(state.pc, 0x0203);
+        state.run_one_instruction().unwrap();
+        assert_eq!(state.pc, 0x4000);
+        assert_eq!(pushed_pc(&mut state), 0x0203);
+        assert!(state.get_flag(flags::I));
+        // released by the device, not by servicing it
+        assert!(state.irq_asserted());
+        state.set_irq(IrqSource::External, false);
+        assert!(!state.irq_asserted());
+    }
+
+    #[test]
+    fn test_sei_and_plp_change_i_one_instruction_late() {
+        // an IRQ coming in during SEI is taken right after it, with I pushed set
+        let mut state = idle_cpu();
+        state.write(0x0200, 0x78);
+        state.run_one_instruction().unwrap();
+        assert_eq!(state.pc, 0x0201);
+        state.set_irq(IrqSource::External, true);
+        state.run_one_instruction().unwrap();
+        assert_eq!(state.pc, 0x4000);
+        assert_eq!(pushed_pc(&mut state), 0x0201);
+        assert_eq!(pushed_status(&mut state), flags::U | flags::I);
+
+        // PLP clearing I, one more instruction runs
+        let mut state = idle_cpu();
+        state.sr |= flags::I;
+        state.write(0x0200, 0x28);
+        state.sp = 0xFC;
+        state.write(0x01FD, flags::U);
+        state.set_irq(IrqSource::External, true);
+        state.run_one_instruction().unwrap();
+        state.run_one_instruction().unwrap();
+        assert_eq!(state.pc, 0x0202);
+        state.run_one_instruction().unwrap();
+        assert_eq!(state.pc, 0x4000);
+
+        // RTI clearing I is seen by the very next poll
+        let mut state = idle_cpu();
+        state.sr |= flags::I;
+        state.write(0x0200, 0x40);
+        state.sp = 0xFA;
+        state.write(0x01FB, flags::U);
+        state.write(0x01FC, 0x05);
+        state.write(0x01FD, 0x02);
+        state.set_irq(IrqSource::External, true);
+        state.run_one_instruction().unwrap();
+        assert_eq!(state.pc, 0x0205);
+        state.run_one_instruction().unwrap();
+        assert_eq!(state.pc, 0x4000);
+        assert_eq!(pushed_pc(&mut state), 0x0205);
+    }
+
+    #[test]
+    fn test_nmi_wins_over_irq() {
+        let mut state = idle_cpu();
+        state.set_irq(IrqSource::External, true);
+        state.trigger_nmi();
+        state.run_one_instruction().unwrap();
+        assert_eq!(state.pc, 0x3000);
+        assert!(!state.nmi_pending);
+        // the NMI set I, so the IRQ waits
+        assert!(state.irq_asserted());
+        state.write(0x3000, 0xEA);
+        state.run_one_instruction().unwrap();
+        assert_eq!(state.pc, 0x3001);
+    }
+
+    #[test]
+    fn test_b_flag_only_pushed_by_brk() {
+        let mut state = idle_cpu();
+        state.write(0x0200, 0x00);
+        state.run_one_instruction().unwrap();
+        assert_eq!(state.pc, 0x4000);
+        // BRK skips its padding byte
+        assert_eq!(pushed_pc(&mut state), 0x0202);
+        assert_eq!(pushed_status(&mut state), flags::U | flags::B);
+
+        let mut state = idle_cpu();
+        state.trigger_nmi();
+        state.run_one_instruction().unwrap();
+        assert_eq!(pushed_pc(&mut state), 0x0200);
+        assert_eq!(pushed_status(&mut state), flags::U);
+
+        let mut state = idle_cpu();
+        state.sr = flags::C;
+        state.set_irq(IrqSource::External, true);
+        state.run_one_instruction().unwrap();
+        assert_eq!(pushed_status(&mut state), flags::U | flags::C);
+    }
+
+    #[test]
+    fn test_rti_and_plp_clear_b() {
+        // RTI to $0205 with every flag set on the stack
+        let mut state = idle_cpu();
+        state.write(0x0200, 0x40);
+        state.sp = 0xFA;
+        state.write(0x01FB, 0xFF);
+        state.write(0x01FC, 0x05);
+        state.write(0x01FD, 0x02);
+        state.run_one_instruction().unwrap();
+        assert_eq!(state.pc, 0x0205);
+        assert_eq!(state.sr, !flags::B);
+        assert_eq!(state.sp, 0xFD);
+
+        // PLP of a status with neither B nor U
+        let mut state = idle_cpu();
+        state.write(0x0200, 0x28);
+        state.sp = 0xFC;
+        state.write(0x01FD, flags::N);
+        state.run_one_instruction().unwrap();
+        assert_eq!(state.sr, flags::N | flags::U);
+    }
+
+    #[test]
+    fn test_interrupts_take_7_cycles() {
+        let mut state = idle_cpu();
+        state.trigger_nmi();
+        state.run_one_instruction().unwrap();
+        assert_eq!(state.cycles, 7);
+
+        let mut state = idle_cpu();
+        state.set_irq(IrqSource::External, true);
+        state.run_one_instruction().unwrap();
+        assert_eq!(state.cycles, 7);
+
+        let mut state = idle_cpu();
+        state.write(0x0200, 0x00);
+        state.run_one_instruction().unwrap();
+        assert_eq!(state.cycles, 7);
+    }
+}
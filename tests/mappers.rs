@@ -0,0 +1,316 @@
+mod common;
+
+#[cfg(test)]
+mod test_mappers {
+    use asmnes::AsmnesError;
+    use remun::FileError;
+    use remun::State;
+    use remun::mappers;
+    use remun::ppu::Mirroring;
+
+    use crate::common::assemble;
+
+    #[test]
+    fn test_unsupported_mapper() -> Result<(), AsmnesError> {
+        let ines = assemble(".inesmap 255\n.inesmir 0\n.inesprg 1\n.ineschr 1\n")?;
+        assert!(matches!(
+            State::new(ines),
+            Err(FileError::UnsupportedMapper(255))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_nrom_256() -> Result<(), AsmnesError> {
+        let ines = assemble(
+            "
+.inesmap 0
+.inesmir 0
+.inesprg 2
+.ineschr 1
+.bank 0
+.org $8000
+.db $10
+.bank 1
+.org $A000
+.db $11
+.bank 2
+.org $C000
+.db $12
+.bank 3
+.org $E000
+.db $13
+.org $FFFC
+.db $00
+.db $C0
+.bank 4
+.org $0000
+.db $14
+",
+        )?;
+        let mut state = State::new(ines).unwrap();
+        assert_eq!(state.pc, 0xC000);
+        assert_eq!(
+            [0x8000, 0xA000, 0xC000, 0xE000].map(|a| state.read(a, true)),
+            [0x10, 0x11, 0x12, 0x13]
+        );
+        assert_eq!(state.ppu_read(0x0000, true), 0x14);
+        // writes to ROM go nowhere
+        state.write(0x8000, 0xFF);
+        state.ppu_write(0x0000, 0xFF);
+        assert_eq!(state.read(0x8000, true), 0x10);
+        assert_eq!(state.ppu_read(0x0000, true), 0x14);
+        Ok(())
+    }
+
+    #[test]
+    fn test_chr_ram() -> Result<(), AsmnesError> {
+        let ines = assemble(".inesmap 0\n.inesmir 0\n.inesprg 1\n.ineschr 0\n")?;
+        let mut state = State::new(ines).unwrap();
+        state.ppu_write(0x0000, 0xAB);
+        state.ppu_write(0x1FFF, 0xCD);
+        assert_eq!(state.ppu_read(0x0000, true), 0xAB);
+        assert_eq!(state.ppu_read(0x1FFF, true), 0xCD);
+        Ok(())
+    }
+
+    /// 64KiB of PRG with the bank number at the start of every 8KiB bank, and
+    /// `chr` 8KiB CHR banks with $40 + the 4KiB bank number at the start of each.
+    /// `program` is at $E010 in the last bank of both 32KiB halves.
+    fn banked_rom(mapper: u8, chr: u8, program: &str) -> String {
+        let mut source = format!(".inesmap {mapper}\n.inesmir 0\n.inesprg 4\n.ineschr {chr}\n");
+        for bank in 0..8 {
+            let org = 0x8000 + (bank % 4) * 0x2000;
+            source += &format!(".bank {bank}\n.org ${org:04X}\n.db ${bank:02X}\n");
+            if bank % 4 == 3 {
+                source += &format!(".org $FFFC\n.db $10\n.db $E0\n.org $E010\n{program}");
+            }
+        }
+        for bank in 0..chr as u16 * 2 {
+            let org = (bank % 2) * 0x1000;
+            let value = 0x40 + bank;
+            source += &format!(
+                ".bank {}\n.org ${org:04X}\n.db ${value:02X}\n",
+                8 + bank / 2
+            );
+        }
+        source
+    }
+
+    fn prg(state: &mut State) -> [u8; 4] {
+        [0x8000, 0xA000, 0xC000, 0xE000].map(|a| state.read(a, true))
+    }
+
+    fn chr(state: &mut State) -> [u8; 2] {
+        [0x0000, 0x1000].map(|a| state.ppu_read(a, true))
+    }
+
+    /// Writes the low 5 bits of `value` to an MMC1 register one bit at a time,
+    /// a few cycles apart so none of them get ignored.
+    fn mmc1_write(state: &mut State, address: u16, value: u8) {
+        for bit in 0..5 {
+            state.cycles += 2;
+            state.write(address, (value >> bit) & 1);
+        }
+    }
+
+    #[test]
+    fn test_mmc1_prg_modes() -> Result<(), AsmnesError> {
+        let mut state = State::new(assemble(&banked_rom(1, 2, ""))?).unwrap();
+        // the last bank is fixed at $C000 on power-up
+        assert_eq!(state.pc, 0xE010);
+        assert_eq!(prg(&mut state), [0, 1, 6, 7]);
+        mmc1_write(&mut state, 0xE000, 2);
+        assert_eq!(prg(&mut state), [4, 5, 6, 7]);
+        // first bank fixed at $8000
+        mmc1_write(&mut state, 0x8000, 0b01000);
+        assert_eq!(prg(&mut state), [0, 1, 4, 5]);
+        // 32KiB, the low bit of the bank is ignored
+        mmc1_write(&mut state, 0x8000, 0b00000);
+        mmc1_write(&mut state, 0xE000, 3);
+        assert_eq!(prg(&mut state), [4, 5, 6, 7]);
+        // a write with bit 7 set resets the shift register and the PRG mode
+        state.write(0x8000, 1);
+        state.cycles += 2;
+        state.write(0x8000, 0x80);
+        mmc1_write(&mut state, 0xE000, 1);
+        assert_eq!(prg(&mut state), [2, 3, 6, 7]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mmc1_chr_banks() -> Result<(), AsmnesError> {
+        let mut state = State::new(assemble(&banked_rom(1, 2, ""))?).unwrap();
+        assert_eq!(chr(&mut state), [0x40, 0x41]);
+        // 8KiB mode ignores the low bit
+        mmc1_write(&mut state, 0xA000, 3);
+        assert_eq!(chr(&mut state), [0x42, 0x43]);
+        // two 4KiB banks
+        mmc1_write(&mut state, 0x8000, 0b11100);
+        mmc1_write(&mut state, 0xA000, 3);
+        mmc1_write(&mut state, 0xC000, 0);
+        assert_eq!(chr(&mut state), [0x43, 0x40]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mmc1_consecutive_writes() -> Result<(), AsmnesError> {
+        // INC writes 7 and then 8 on the next cycle, only the 1 bit counts
+        let program = "INC $E000\nLDA #0\nSTA $E000\nSTA $E000\nSTA $E000\nSTA $E000\n";
+        let mut state = State::new(assemble(&banked_rom(1, 2, program))?).unwrap();
+        state.run_instructions(5).unwrap();
+        assert_eq!(prg(&mut state), [0, 1, 6, 7]);
+        state.run_instructions(1).unwrap();
+        assert_eq!(prg(&mut state), [2, 3, 6, 7]);
+        // the same goes for a reset, the second write does not start a new shift
+        state.write(0xE000, 0x80);
+        state.write(0xE000, 1);
+        mmc1_write(&mut state, 0xE000, 0);
+        assert_eq!(prg(&mut state), [0, 1, 6, 7]);
+        // winding the cycle counter back, e.g. from a debugger, is fine
+        state.cycles = 0;
+        mmc1_write(&mut state, 0xE000, 1);
+        assert_eq!(prg(&mut state), [2, 3, 6, 7]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mmc1_mirroring_and_prg_ram() -> Result<(), AsmnesError> {
+        let mut state = State::new(assemble(&banked_rom(1, 2, ""))?).unwrap();
+        // the header mirroring stays until control is written, not just reset
+        assert_eq!(state.ppu_state.mirroring, Mirroring::Horizontal);
+        state.write(0x8000, 0x80);
+        assert_eq!(state.ppu_state.mirroring, Mirroring::Horizontal);
+        for (control, mirroring) in [
+            (0, Mirroring::SingleScreenLower),
+            (1, Mirroring::SingleScreenUpper),
+            (2, Mirroring::Vertical),
+            (3, Mirroring::Horizontal),
+        ] {
+            mmc1_write(&mut state, 0x8000, 0b01100 | control);
+            assert_eq!(state.ppu_state.mirroring, mirroring);
+        }
+        assert!(state.mapper.prg_ram_enabled());
+        mmc1_write(&mut state, 0xE000, 0x10);
+        assert!(!state.mapper.prg_ram_enabled());
+        Ok(())
+    }
+
+    /// Runs every line of `program` in a ROM from `banked_rom`.
+    fn run_banked(mapper: u8, chr: u8, program: &str) -> Result<State, AsmnesError> {
+        let mut state = State::new(assemble(&banked_rom(mapper, chr, program))?).unwrap();
+        state
+            .run_instructions(program.lines().count() as u64)
+            .unwrap();
+        Ok(state)
+    }
+
+    #[test]
+    fn test_uxrom() -> Result<(), AsmnesError> {
+        let mut state = run_banked(2, 0, "")?;
+        assert_eq!(prg(&mut state), [0, 1, 6, 7]);
+        // CHR-RAM
+        state.ppu_write(0x1000, 0x5A);
+        assert_eq!(state.ppu_read(0x1000, true), 0x5A);
+        let mut state = run_banked(2, 0, "LDA #$02\nSTA $8000\n")?;
+        assert_eq!(prg(&mut state), [4, 5, 6, 7]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_uxrom_bus_conflicts() -> Result<(), AsmnesError> {
+        let program = "LDA #$02\nSTA $8000\nLDA #$03\nSTA $E000\n";
+        let ines = assemble(&banked_rom(2, 0, program))?;
+        let mut state = State::new(ines.clone()).unwrap();
+        state.set_mapper(Box::new(mappers::UxRom::new(&ines, true)));
+        // $8000 holds 0, so the first write selects bank 0
+        state.run_instructions(2).unwrap();
+        assert_eq!(prg(&mut state), [0, 1, 6, 7]);
+        // $E000 holds 7, which lets 3 through
+        state.run_instructions(2).unwrap();
+        assert_eq!(prg(&mut state), [6, 7, 6, 7]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cnrom() -> Result<(), AsmnesError> {
+        let mut state = run_banked(3, 4, "")?;
+        assert_eq!(prg(&mut state), [0, 1, 2, 3]);
+        assert_eq!(chr(&mut state), [0x40, 0x41]);
+        let mut state = run_banked(3, 4, "LDA #$03\nSTA $8000\n")?;
+        assert_eq!(chr(&mut state), [0x46, 0x47]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_axrom() -> Result<(), AsmnesError> {
+        let mut state = run_banked(7, 0, "")?;
+        assert_eq!(prg(&mut state), [0, 1, 2, 3]);
+        assert_eq!(state.ppu_state.mirroring, Mirroring::SingleScreenLower);
+        let mut state = run_banked(7, 0, "LDA #$11\nSTA $8000\n")?;
+        assert_eq!(prg(&mut state), [4, 5, 6, 7]);
+        assert_eq!(state.ppu_state.mirroring, Mirroring::SingleScreenUpper);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mmc3_banks() -> Result<(), AsmnesError> {
+        let mut state = run_banked(4, 4, "")?;
+        assert_eq!(prg(&mut state), [0, 1, 6, 7]);
+        // R6, then the other PRG mode swaps $8000 and $C000
+        state.write(0x8000, 6);
+        state.write(0x8001, 3);
+        assert_eq!(prg(&mut state), [3, 1, 6, 7]);
+        state.write(0x8000, 0x40);
+        assert_eq!(prg(&mut state), [6, 1, 3, 7]);
+        // R0 is 2KiB at $0000, R2 1KiB at $1000, and inverted
+        state.write(0x8000, 0);
+        state.write(0x8001, 5);
+        state.write(0x8000, 2);
+        state.write(0x8001, 8);
+        assert_eq!(chr(&mut state), [0x41, 0x42]);
+        state.write(0x8000, 0x80);
+        assert_eq!(chr(&mut state), [0x42, 0x41]);
+        // mirroring
+        state.write(0xA000, 1);
+        assert_eq!(state.ppu_state.mirroring, Mirroring::Horizontal);
+        state.write(0xA000, 0);
+        assert_eq!(state.ppu_state.mirroring, Mirroring::Vertical);
+        // PRG-RAM protect
+        state.write(0xA001, 0xC0);
+        assert!(state.mapper.prg_ram_enabled() && !state.mapper.prg_ram_writable());
+        state.write(0xA001, 0x00);
+        assert!(!state.mapper.prg_ram_enabled());
+        Ok(())
+    }
+
+    #[test]
+    fn test_mmc3_scanline_irq() -> Result<(), AsmnesError> {
+        // latch 10, reload and enable, then render with sprites at $1000
+        let program = "LDA #$0A
+STA $C000
+STA $C001
+STA $E001
+LDA #$08
+STA $2000
+LDA #$18
+STA $2001
+JMP $E025
+";
+        let mut state = run_banked(4, 4, program)?;
+        let next_irq = |state: &mut State| {
+            while !state.irq_asserted() {
+                state.run_one_instruction().unwrap();
+            }
+            state.ppu_state.scanline
+        };
+        // reloaded on the first line, then counts down to 0
+        assert_eq!(next_irq(&mut state), 10);
+        // acknowledged by $E000, which also disables it
+        state.write(0xE000, 0);
+        assert!(!state.irq_asserted());
+        state.write(0xE001, 0);
+        assert_eq!(next_irq(&mut state), 21);
+        Ok(())
+    }
+}
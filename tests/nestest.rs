@@ -0,0 +1,51 @@
+//! Runs nestest.nes in automation mode and compares the CPU trace with the
+//! reference log, https://www.nesdev.org/wiki/Emulator_tests
+//! The ROM and the log are not part of the repository, put them at
+//! `tests/nestest/nestest.nes` and `tests/nestest/nestest.log` and run
+//! `cargo test --test nestest -- --ignored`.
+#[cfg(test)]
+mod test_nestest {
+    use remun::State;
+    use remun::trace::trace_line;
+    use std::fs;
+    use std::path::Path;
+
+    /// The PC and everything from the registers on, PPU position and cycles
+    /// included. The disassembly is left out since the log annotates it with
+    /// the memory it accesses.
+    fn comparable(line: &str) -> Vec<&str> {
+        let mut fields = vec![line.get(0..4).unwrap_or(line)];
+        if let Some(registers) = line.rfind(" A:") {
+            fields.extend(line[registers..].split_whitespace());
+        }
+        fields
+    }
+
+    #[test]
+    #[ignore = "needs tests/nestest/nestest.nes and nestest.log"]
+    fn test_nestest_log() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/nestest");
+        let (rom, log) = (dir.join("nestest.nes"), dir.join("nestest.log"));
+        assert!(
+            rom.exists() && log.exists(),
+            "nestest.nes and nestest.log not found in {dir:?}"
+        );
+        let mut state = State::new(remun::load_from_file(rom).unwrap()).unwrap();
+        // automation mode
+        state.pc = 0xC000;
+        let log = fs::read_to_string(log).unwrap();
+        for (line_number, expected) in log.lines().enumerate() {
+            let got = trace_line(&mut state);
+            assert_eq!(
+                comparable(&got),
+                comparable(expected),
+                "first divergence on line {}:\nexpected: {expected}\ngot:      {got}",
+                line_number + 1
+            );
+            state.run_one_instruction().unwrap();
+        }
+        // nestest reports failures at $02 (official) and $03 (unofficial)
+        assert_eq!(state.read(0x0002, true), 0x00);
+        assert_eq!(state.read(0x0003, true), 0x00);
+    }
+}
@@ -0,0 +1,275 @@
+mod common;
+
+#[cfg(test)]
+mod test_ppu {
+    use asmnes::AsmnesError;
+    use remun::State;
+    use remun::ppu;
+
+    use crate::common;
+
+    /// NROM-128, reset at $C000 and NMI at $C100, which counts NMIs in $00.
+    /// Tile 1 has its top left pixel set, tile 2 is a filled square.
+    const HEADER: &str = "
+.inesmap 0
+.inesmir 1
+.inesprg 1
+.ineschr 1
+.bank 1
+.org $FFFA
+.db $00
+.db $C1
+.db $00
+.db $C0
+.bank 2
+.org $0010
+.db $80
+.org $0020
+.db $FF
+.db $FF
+.db $FF
+.db $FF
+.db $FF
+.db $FF
+.db $FF
+.db $FF
+.bank 0
+.org $C100
+INC $00
+RTI
+.org $C000
+";
+
+    fn load(program: &str) -> Result<State, AsmnesError> {
+        let ines = common::assemble(&format!("{HEADER}{program}"))?;
+        Ok(State::new(ines).unwrap())
+    }
+
+    #[test]
+    fn test_vblank_nmi() -> Result<(), AsmnesError> {
+        let mut state = load("LDA #$80\nSTA $2000\nJMP $C005\n")?;
+        state.run_frames(3).unwrap();
+        assert_eq!(state.ppu_state.frame, 3);
+        assert_eq!(state.ppu_state.scanline, ppu::VBLANK_SCANLINE);
+        assert!(state.ppu_state.vblank);
+        // the NMI of the last frame is still pending
+        assert_eq!(state.read(0x0000, true), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_background_and_sprites() -> Result<(), AsmnesError> {
+        let mut state = load("LDA #$1E\nSTA $2001\nJMP $C005\n")?;
+        state.ppu_write(0x2000, 0x01);
+        state.ppu_write(0x2001, 0x02);
+        state.ppu_write(0x3F00, 0x0F);
+        state.ppu_write(0x3F01, 0x30);
+        state.ppu_write(0x3F11, 0x16);
+        // sprite 0 with tile 1 at (8, 1), over the filled background tile
+        state.ppu_state.oam[0..4].copy_from_slice(&[0x00, 0x01, 0x00, 0x08]);
+        state.run_frames(2).unwrap();
+        let pixel = |x: usize, y: usize| state.ppu_state.framebuffer[y * ppu::WIDTH + x];
+        assert_eq!(pixel(0, 0), 0x30);
+        assert_eq!(pixel(1, 0), 0x0F);
+        assert_eq!(pixel(8, 0), 0x30);
+        assert_eq!(pixel(8, 1), 0x16);
+        assert_eq!(pixel(9, 1), 0x30);
+        assert_eq!(pixel(100, 100), 0x0F);
+        assert!(state.ppu_state.sprite_0_hit);
+        assert!(!state.ppu_state.sprite_overflow);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rendering_disabled_shows_backdrop() -> Result<(), AsmnesError> {
+        let mut state = load("JMP $C000\n")?;
+        state.ppu_write(0x3F00, 0x21);
+        state.run_frames(1).unwrap();
+        assert!(state.ppu_state.framebuffer.iter().all(|p| *p == 0x21));
+        Ok(())
+    }
+
+    /// Runs every line of `program` as one instruction.
+    fn run(program: &str) -> Result<State, AsmnesError> {
+        let mut state = load(program)?;
+        state
+            .run_instructions(program.lines().count() as u64)
+            .unwrap();
+        Ok(state)
+    }
+
+    #[test]
+    fn test_ppudata_increments() -> Result<(), AsmnesError> {
+        let mut state = run("LDA #$3F
+STA $2006
+LDA #$00
+STA $2006
+LDA #$0F
+STA $2007
+LDA #$30
+STA $2007
+LDA #$04
+STA $2000
+LDA #$20
+STA $2006
+LDA #$00
+STA $2006
+LDA #$01
+STA $2007
+LDA #$02
+STA $2007
+")?;
+        assert_eq!(state.ppu_read(0x3F00, true), 0x0F);
+        assert_eq!(state.ppu_read(0x3F01, true), 0x30);
+        assert_eq!(state.ppu_read(0x2000, true), 0x01);
+        assert_eq!(state.ppu_read(0x2020, true), 0x02);
+        assert_eq!(state.ppu_state.v, 0x2040);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ppudata_reads_are_buffered() -> Result<(), AsmnesError> {
+        let mut state = load(
+            "LDA #$20
+STA $2006
+LDA #$20
+STA $2006
+LDA $2007
+STA $10
+LDA $2007
+STA $11
+LDA #$3F
+STA $2006
+LDA #$00
+STA $2006
+LDA $2007
+STA $12
+",
+        )?;
+        state.ppu_write(0x2020, 0xAA);
+        state.ppu_write(0x2021, 0xBB);
+        state.ppu_write(0x3F00, 0x21);
+        state.run_instructions(14).unwrap();
+        assert_eq!(state.read(0x0011, true), 0xAA);
+        // palettes are not delayed
+        assert_eq!(state.read(0x0012, true), 0x21);
+        Ok(())
+    }
+
+    #[test]
+    fn test_oam_access() -> Result<(), AsmnesError> {
+        let mut state = run("LDA #$05
+STA $2003
+LDA #$AB
+STA $2004
+LDA #$FF
+STA $2004
+LDA #$05
+STA $2003
+LDA $2004
+STA $10
+LDA #$06
+STA $2003
+LDA $2004
+STA $11
+")?;
+        assert_eq!(state.ppu_state.oam[5], 0xAB);
+        assert_eq!(state.read(0x0010, true), 0xAB);
+        // the attribute byte of sprite 1
+        assert_eq!(state.read(0x0011, true), 0xE3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_scroll_registers() -> Result<(), AsmnesError> {
+        // PPUSTATUS resets the toggle, X = 4 and Y = 13
+        let state = run("LDA $2002
+LDA #$04
+STA $2005
+LDA #$0D
+STA $2005
+")?;
+        assert_eq!(state.ppu_state.x, 4);
+        assert_eq!(state.ppu_state.t, 0x5020);
+        assert!(!state.ppu_state.w);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fine_x_scroll() -> Result<(), AsmnesError> {
+        let mut state = load("LDA #$04\nSTA $2005\nSTA $2005\nLDA #$0A\nSTA $2001\nJMP $C00D\n")?;
+        state.ppu_write(0x2000, 0x01);
+        state.ppu_write(0x2001, 0x02);
+        state.ppu_write(0x3F00, 0x0F);
+        state.ppu_write(0x3F01, 0x30);
+        state.run_frames(2).unwrap();
+        let pixel = |x: usize, y: usize| state.ppu_state.framebuffer[y * ppu::WIDTH + x];
+        // scrolled up and left by 4, tile 2 starts at x 4 on the top line
+        assert_eq!(pixel(0, 0), 0x0F);
+        assert_eq!(pixel(3, 0), 0x0F);
+        assert_eq!(pixel(4, 0), 0x30);
+        assert_eq!(pixel(11, 3), 0x30);
+        assert_eq!(pixel(12, 0), 0x0F);
+        assert_eq!(pixel(4, 4), 0x0F);
+        Ok(())
+    }
+
+    #[test]
+    fn test_oam_dma() -> Result<(), AsmnesError> {
+        // The write ends on cycle 13, odd
+        let mut state = load("LDA #$02\nSTA $4014\n")?;
+        for i in 0..=0xFF {
+            state.write(0x0200 + i, i as u8);
+        }
+        state.run_instructions(2).unwrap();
+        assert!(
+            state
+                .ppu_state
+                .oam
+                .iter()
+                .enumerate()
+                .all(|(i, v)| i as u8 == *v)
+        );
+        assert_eq!(state.cycles, 13 + 514);
+        // and on cycle 16, even
+        let state = run("LDA $00\nLDA #$02\nSTA $4014\n")?;
+        assert_eq!(state.cycles, 16 + 513);
+        Ok(())
+    }
+
+    #[test]
+    fn test_nametable_mirroring() -> Result<(), AsmnesError> {
+        // the header asks for vertical mirroring
+        let mut state = load("JMP $C000\n")?;
+        assert_eq!(state.ppu_state.mirroring, ppu::Mirroring::Vertical);
+        state.ppu_write(0x2005, 0x11);
+        state.ppu_write(0x2405, 0x22);
+        assert_eq!(state.ppu_read(0x2805, true), 0x11);
+        assert_eq!(state.ppu_read(0x2C05, true), 0x22);
+        // $3000-$3EFF mirrors $2000-$2EFF
+        assert_eq!(state.ppu_read(0x3405, true), 0x22);
+        state.ppu_write(0x3E00, 0x33);
+        assert_eq!(state.ppu_read(0x2600, true), 0x33);
+
+        // the same CIRAM seen through the other modes
+        state.ppu_state.mirroring = ppu::Mirroring::Horizontal;
+        assert_eq!(state.ppu_read(0x2005, true), 0x11);
+        assert_eq!(state.ppu_read(0x2405, true), 0x11);
+        assert_eq!(state.ppu_read(0x2805, true), 0x22);
+        assert_eq!(state.ppu_read(0x2C05, true), 0x22);
+        state.ppu_state.mirroring = ppu::Mirroring::SingleScreenUpper;
+        assert_eq!(state.ppu_read(0x2005, true), 0x22);
+        state.ppu_state.mirroring = ppu::Mirroring::SingleScreenLower;
+        assert_eq!(state.ppu_read(0x2C05, true), 0x11);
+
+        // four-screen has its own RAM for the last two
+        state.ppu_state.mirroring = ppu::Mirroring::FourScreen;
+        state.ppu_write(0x2805, 0x44);
+        state.ppu_write(0x2C05, 0x55);
+        assert_eq!(
+            [0x2005, 0x2405, 0x2805, 0x2C05].map(|a| state.ppu_read(a, true)),
+            [0x11, 0x22, 0x44, 0x55]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,176 @@
+//! Single-step tests in the ProcessorTests/SingleStepTests format,
+//! https://github.com/SingleStepTests/65x02 (the nes6502 set).
+//! Every `.json` file in `tests/processor_tests` holds a list of vectors, each
+//! running exactly one instruction on a CPU with flat 64KiB RAM. The
+//! registers, the RAM and every bus access, in order, are compared.
+//! `<opcode>.json` are written by `generate.py`, see there, and cover every
+//! opcode but JAM including page crossings and taken branches. Other files
+//! hold hand-written vectors. The complete suite can be dropped into the same
+//! directory.
+#[cfg(test)]
+mod test_processor_tests {
+    use remun::AddressSpace;
+    use remun::BusDevice;
+    use remun::State;
+    use serde::Deserialize;
+    use shared::CODEPOINTS;
+    use shared::Opcode;
+    use std::cell::RefCell;
+    use std::fs;
+    use std::path::Path;
+    use std::rc::Rc;
+
+    #[derive(Deserialize)]
+    struct Vector {
+        name: String,
+        initial: CpuState,
+        #[serde(rename = "final")]
+        expected: CpuState,
+        /// Bus activity, one entry per cycle: address, value, "read"/"write".
+        cycles: Vec<(u16, u8, String)>,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct CpuState {
+        pc: u16,
+        s: u8,
+        a: u8,
+        x: u8,
+        y: u8,
+        p: u8,
+        ram: Vec<(u16, u8)>,
+    }
+
+    /// Bus activity in the format of `Vector::cycles`.
+    type Log = Rc<RefCell<Vec<(u16, u8, String)>>>;
+
+    /// Flat 64KiB RAM that logs every access, peeks are not logged.
+    struct LoggingRam {
+        bytes: Vec<u8>,
+        log: Log,
+    }
+
+    impl BusDevice for LoggingRam {
+        fn read(&mut self, offset: u16) -> u8 {
+            let value = self.bytes[offset as usize];
+            self.log
+                .borrow_mut()
+                .push((offset, value, String::from("read")));
+            value
+        }
+        fn write(&mut self, offset: u16, value: u8) {
+            self.bytes[offset as usize] = value;
+            self.log
+                .borrow_mut()
+                .push((offset, value, String::from("write")));
+        }
+        fn peek(&self, offset: u16) -> u8 {
+            self.bytes[offset as usize]
+        }
+    }
+
+    /// Runs one vector, returns a description of what differed.
+    fn run_vector(vector: &Vector) -> Result<(), String> {
+        let mut state = State::new_flat_ram();
+        let CpuState {
+            pc,
+            s,
+            a,
+            x,
+            y,
+            p,
+            ref ram,
+        } = vector.initial;
+        (state.pc, state.sp, state.a, state.x, state.y, state.sr) = (pc, s, a, x, y, p);
+        let mut bytes = vec![0; 0x10000];
+        for (address, value) in ram {
+            bytes[*address as usize] = *value;
+        }
+        let log = Log::default();
+        let device = LoggingRam {
+            bytes,
+            log: log.clone(),
+        };
+        state.map_device(AddressSpace::Cpu, 0x0000..=0xFFFF, Box::new(device));
+        state.run_one_instruction().map_err(|e| e.to_string())?;
+        let got = CpuState {
+            pc: state.pc,
+            s: state.sp,
+            a: state.a,
+            x: state.x,
+            y: state.y,
+            p: state.sr,
+            ram: vector
+                .expected
+                .ram
+                .iter()
+                .map(|(address, _)| (*address, state.read(*address, true)))
+                .collect(),
+        };
+        if got != vector.expected {
+            return Err(format!("expected {:?}, got {got:?}", vector.expected));
+        }
+        if *log.borrow() != vector.cycles {
+            return Err(format!(
+                "expected bus activity {:?}, got {:?}",
+                vector.cycles,
+                log.borrow()
+            ));
+        }
+        if state.cycles != vector.cycles.len() as u64 {
+            return Err(format!(
+                "expected {} cycles, got {}",
+                vector.cycles.len(),
+                state.cycles
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_single_step_vectors() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/processor_tests");
+        let mut paths: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| p.extension().is_some_and(|e| e == "json"))
+            .collect();
+        paths.sort();
+        assert!(!paths.is_empty(), "no test vectors in {dir:?}");
+        let mut failures = Vec::new();
+        let mut covered = [false; 256];
+        for path in paths {
+            let vectors: Vec<Vector> =
+                serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+            for vector in vectors.iter() {
+                // JAM halts the CPU, there is no final state to compare with
+                let opcode = vector
+                    .initial
+                    .ram
+                    .iter()
+                    .find(|(address, _)| *address == vector.initial.pc)
+                    .map(|(_, value)| *value);
+                if let Some(opcode) = opcode {
+                    if CODEPOINTS[opcode as usize].opcode == Opcode::JAM {
+                        continue;
+                    }
+                    covered[opcode as usize] = true;
+                }
+                if let Err(e) = run_vector(vector) {
+                    failures.push(format!("{}: {}: {e}", path.display(), vector.name));
+                }
+            }
+        }
+        let missing: Vec<String> = (0..256)
+            .filter(|i| !covered[*i] && CODEPOINTS[*i].opcode != Opcode::JAM)
+            .map(|i| format!("${i:02X}"))
+            .collect();
+        assert!(missing.is_empty(), "no vectors for {}", missing.join(", "));
+        assert!(
+            failures.is_empty(),
+            "{} failing vectors, first ones:\n{}",
+            failures.len(),
+            failures[..failures.len().min(10)].join("\n")
+        );
+    }
+}
@@ -0,0 +1,396 @@
+"""Generates the single-step vectors in this directory, one <opcode>.json per
+opcode but JAM, in the ProcessorTests/SingleStepTests nes6502 format.
+
+The 6502 model here is independent of remun, only the opcode table is taken
+from shared/lib.rs. It is an NMOS 6502 without decimal mode, doing the
+per-cycle bus accesses documented in 64doc, including the dummy reads and
+the double write of read-modify-write instructions. The unstable opcodes use
+the same magic constant as `State::magic`.
+
+Every opcode gets two random vectors, indexed modes one without and one with
+a page cross, branches one not taken, one taken and one taken to another page.
+The random generator is seeded with the opcode, so running
+
+    python3 tests/processor_tests/generate.py
+
+reproduces the files. Hand-written vectors go in other .json files.
+"""
+import json, os, random
+
+ROOT = os.path.dirname(os.path.abspath(__file__))
+src = open(os.path.join(ROOT, '../../shared/lib.rs')).read()
+table = src[src.index('pub const CODEPOINTS'):]
+table = table[table.index('tabalize! [') + 11:table.index('];')]
+CP = [tuple(x.strip() for x in e.split(',')) for e in table.replace('\n', ' ').split(';') if e.strip()]
+assert len(CP) == 256
+
+N, V, U, B, D, I, Z, C = 0x80, 0x40, 0x20, 0x10, 0x08, 0x04, 0x02, 0x01
+MAGIC = 0xEE
+
+class Retry(Exception):
+    pass
+
+class Cpu:
+    def __init__(self, rng):
+        self.rng = rng
+        self.mem = {}
+        self.init = {}
+        self.log = []
+        self.placed = set()
+
+    def place(self, a, v):
+        a &= 0xFFFF
+        if a in self.mem and self.mem[a] != v:
+            raise Retry()
+        self.mem[a] = v
+        self.init[a] = v
+        self.placed.add(a)
+
+    def peek(self, a):
+        if a not in self.mem:
+            v = self.rng.randrange(256)
+            self.mem[a] = v
+            self.init[a] = v
+        return self.mem[a]
+
+    def read(self, a):
+        a &= 0xFFFF
+        v = self.peek(a)
+        self.log.append([a, v, "read"])
+        return v
+
+    def write(self, a, v):
+        a &= 0xFFFF
+        if a in self.placed:
+            raise Retry()
+        self.peek(a)
+        self.mem[a] = v & 0xFF
+        self.log.append([a, v & 0xFF, "write"])
+
+    def flag(self, f):
+        return self.p & f != 0
+
+    def setf(self, f, on):
+        self.p = (self.p | f) if on else (self.p & ~f & 0xFF)
+
+    def nz(self, v):
+        v &= 0xFF
+        self.setf(Z, v == 0)
+        self.setf(N, v & 0x80)
+        return v
+
+    def push(self, v):
+        self.write(0x100 | self.s, v)
+        self.s = (self.s - 1) & 0xFF
+
+def adc(c, a, m):
+    s = a + m + (1 if c.flag(C) else 0)
+    r = s & 0xFF
+    c.setf(C, s > 0xFF)
+    c.setf(V, (~(a ^ m)) & (a ^ r) & 0x80)
+    return c.nz(r)
+
+def cmp(c, reg, m):
+    c.setf(C, reg >= m)
+    c.nz(reg - m)
+
+def asl(c, m):
+    c.setf(C, m & 0x80)
+    return c.nz(m << 1)
+
+def lsr(c, m):
+    c.setf(C, m & 1)
+    return c.nz(m >> 1)
+
+def rol(c, m):
+    r = (m << 1) | (1 if c.flag(C) else 0)
+    c.setf(C, m & 0x80)
+    return c.nz(r)
+
+def ror(c, m):
+    r = (m >> 1) | (0x80 if c.flag(C) else 0)
+    c.setf(C, m & 1)
+    return c.nz(r)
+
+def read_op(c, op, m):
+    if op == 'LDA': c.a = c.nz(m)
+    elif op == 'LDX': c.x = c.nz(m)
+    elif op == 'LDY': c.y = c.nz(m)
+    elif op == 'AND': c.a = c.nz(c.a & m)
+    elif op == 'ORA': c.a = c.nz(c.a | m)
+    elif op == 'EOR': c.a = c.nz(c.a ^ m)
+    elif op == 'ADC': c.a = adc(c, c.a, m)
+    elif op in ('SBC', 'USB'): c.a = adc(c, c.a, m ^ 0xFF)
+    elif op == 'CMP': cmp(c, c.a, m)
+    elif op == 'CPX': cmp(c, c.x, m)
+    elif op == 'CPY': cmp(c, c.y, m)
+    elif op == 'BIT':
+        c.setf(Z, (c.a & m) == 0); c.setf(N, m & 0x80); c.setf(V, m & 0x40)
+    elif op == 'LAX': c.a = c.x = c.nz(m)
+    elif op == 'NOP': pass
+    elif op == 'LAS': c.a = c.x = c.s = c.nz(m & c.s)
+    elif op == 'ANC':
+        c.a = c.nz(c.a & m); c.setf(C, c.a & 0x80)
+    elif op == 'ALR': c.a = lsr(c, c.a & m)
+    elif op == 'ARR':
+        r = ((c.a & m) >> 1) | (0x80 if c.flag(C) else 0)
+        c.a = c.nz(r); c.setf(C, r & 0x40); c.setf(V, ((r >> 6) ^ (r >> 5)) & 1)
+    elif op == 'ANE': c.a = c.nz((c.a | MAGIC) & c.x & m)
+    elif op == 'LXA': c.a = c.x = c.nz((c.a | MAGIC) & m)
+    elif op == 'SBX':
+        t = c.a & c.x
+        c.setf(C, t >= m); c.x = c.nz(t - m)
+    else:
+        raise Exception(op)
+
+def rmw_op(c, op, m):
+    if op == 'ASL': return asl(c, m)
+    if op == 'LSR': return lsr(c, m)
+    if op == 'ROL': return rol(c, m)
+    if op == 'ROR': return ror(c, m)
+    if op == 'INC': return c.nz(m + 1)
+    if op == 'DEC': return c.nz(m - 1)
+    if op == 'SLO':
+        r = asl(c, m); c.a = c.nz(c.a | r); return r
+    if op == 'RLA':
+        r = rol(c, m); c.a = c.nz(c.a & r); return r
+    if op == 'SRE':
+        r = lsr(c, m); c.a = c.nz(c.a ^ r); return r
+    if op == 'RRA':
+        r = ror(c, m); c.a = adc(c, c.a, r); return r
+    if op == 'DCP':
+        r = (m - 1) & 0xFF; cmp(c, c.a, r); return r
+    if op == 'ISC':
+        r = (m + 1) & 0xFF; c.a = adc(c, c.a, r ^ 0xFF); return r
+    raise Exception(op)
+
+READ = {'LDA','LDX','LDY','EOR','AND','ORA','ADC','SBC','CMP','CPX','CPY','BIT','LAX','NOP','LAS',
+        'ANC','ALR','ARR','ANE','LXA','SBX','USB'}
+WRITE = {'STA','STX','STY','SAX','SHA','SHX','SHY','TAS'}
+RMW = {'ASL','LSR','ROL','ROR','INC','DEC','SLO','SRE','RLA','RRA','ISC','DCP'}
+BRANCH = {'BPL': (N, False), 'BMI': (N, True), 'BVC': (V, False), 'BVS': (V, True),
+          'BCC': (C, False), 'BCS': (C, True), 'BNE': (Z, False), 'BEQ': (Z, True)}
+LEN = {'IMPL': 1, 'A': 1, 'IMM': 2, 'ZPG': 2, 'ZPG_X': 2, 'ZPG_Y': 2, 'REL': 2, 'X_IND': 2,
+       'IND_Y': 2, 'ABS': 3, 'ABS_X': 3, 'ABS_Y': 3, 'IND': 3, 'J': 1}
+
+def write_value(c, op, base):
+    h1 = ((base >> 8) + 1) & 0xFF
+    if op == 'STA': return c.a
+    if op == 'STX': return c.x
+    if op == 'STY': return c.y
+    if op == 'SAX': return c.a & c.x
+    if op == 'SHA': return c.a & c.x & h1
+    if op == 'SHX': return c.x & h1
+    if op == 'SHY': return c.y & h1
+    if op == 'TAS':
+        c.s = c.a & c.x
+        return c.a & c.x & h1
+    raise Exception(op)
+
+def execute(c, opcode, op, mode):
+    pc = c.pc
+    c.read(pc)
+    if op in BRANCH:
+        off = c.read(pc + 1)
+        nxt = (pc + 2) & 0xFFFF
+        c.pc = nxt
+        f, want = BRANCH[op]
+        if c.flag(f) == want:
+            c.read(nxt)
+            tgt = (nxt + (off - 256 if off & 0x80 else off)) & 0xFFFF
+            if tgt & 0xFF00 != nxt & 0xFF00:
+                c.read((nxt & 0xFF00) | (tgt & 0xFF))
+            c.pc = tgt
+        return
+    if op == 'BRK':
+        c.read(pc + 1)
+        ret = (pc + 2) & 0xFFFF
+        c.push(ret >> 8); c.push(ret & 0xFF); c.push(c.p | B | U)
+        c.setf(I, True)
+        lo = c.read(0xFFFE); hi = c.read(0xFFFF)
+        c.pc = lo | hi << 8
+        return
+    if op == 'JSR':
+        lo = c.read(pc + 1)
+        c.read(0x100 | c.s)
+        ret = (pc + 2) & 0xFFFF
+        c.push(ret >> 8); c.push(ret & 0xFF)
+        hi = c.read(pc + 2)
+        c.pc = lo | hi << 8
+        return
+    if op in ('RTS', 'RTI', 'PLA', 'PLP', 'PHA', 'PHP'):
+        c.read(pc + 1)
+        c.pc = (pc + 1) & 0xFFFF
+        if op == 'PHA': c.push(c.a); return
+        if op == 'PHP': c.push(c.p | B | U); return
+        c.read(0x100 | c.s)
+        def pull():
+            c.s = (c.s + 1) & 0xFF
+            return c.read(0x100 | c.s)
+        if op == 'PLA': c.a = c.nz(pull()); return
+        if op == 'PLP': c.p = (pull() & ~B & 0xFF) | U; return
+        if op == 'RTI':
+            c.p = (pull() & ~B & 0xFF) | U
+            lo = pull(); hi = pull()
+            c.pc = lo | hi << 8
+            return
+        lo = pull(); hi = pull()
+        ret = lo | hi << 8
+        c.read(ret)
+        c.pc = (ret + 1) & 0xFFFF
+        return
+    if op == 'JMP':
+        lo = c.read(pc + 1); hi = c.read(pc + 2)
+        a = lo | hi << 8
+        if mode == 'IND':
+            lo = c.read(a); hi = c.read((a & 0xFF00) | ((a + 1) & 0xFF))
+            a = lo | hi << 8
+        c.pc = a
+        return
+    if mode in ('IMPL', 'A'):
+        c.read(pc + 1)
+        c.pc = (pc + 1) & 0xFFFF
+        if mode == 'A':
+            c.a = rmw_op(c, op, c.a)
+            return
+        impl(c, op)
+        return
+    c.pc = (pc + LEN[mode]) & 0xFFFF
+    if mode == 'IMM':
+        m = c.read(pc + 1)
+        read_op(c, op, m)
+        return
+    # effective address
+    base = None
+    dummy = None
+    if mode == 'ZPG':
+        a = c.read(pc + 1)
+    elif mode in ('ZPG_X', 'ZPG_Y'):
+        z = c.read(pc + 1)
+        c.read(z)
+        a = (z + (c.x if mode == 'ZPG_X' else c.y)) & 0xFF
+    elif mode == 'ABS':
+        a = c.read(pc + 1) | c.read(pc + 2) << 8
+    elif mode in ('ABS_X', 'ABS_Y'):
+        base = c.read(pc + 1) | c.read(pc + 2) << 8
+        a = (base + (c.x if mode == 'ABS_X' else c.y)) & 0xFFFF
+        dummy = (base & 0xFF00) | (a & 0xFF)
+    elif mode == 'X_IND':
+        z = c.read(pc + 1)
+        c.read(z)
+        z = (z + c.x) & 0xFF
+        a = c.read(z) | c.read((z + 1) & 0xFF) << 8
+    elif mode == 'IND_Y':
+        z = c.read(pc + 1)
+        base = c.read(z) | c.read((z + 1) & 0xFF) << 8
+        a = (base + c.y) & 0xFFFF
+        dummy = (base & 0xFF00) | (a & 0xFF)
+    else:
+        raise Exception(mode)
+    if op in READ:
+        if dummy is not None and dummy != a:
+            c.read(dummy)
+        read_op(c, op, c.read(a))
+    elif op in WRITE:
+        if dummy is not None:
+            c.read(dummy)
+        v = write_value(c, op, base if base is not None else a)
+        if op in ('SHA', 'SHX', 'SHY', 'TAS') and dummy != a:
+            a = (v << 8) | (a & 0xFF)
+        c.write(a, v)
+    elif op in RMW:
+        if dummy is not None:
+            c.read(dummy)
+        m = c.read(a)
+        c.write(a, m)
+        c.write(a, rmw_op(c, op, m))
+    else:
+        raise Exception(op)
+
+def impl(c, op):
+    if op == 'TAX': c.x = c.nz(c.a)
+    elif op == 'TAY': c.y = c.nz(c.a)
+    elif op == 'TXA': c.a = c.nz(c.x)
+    elif op == 'TYA': c.a = c.nz(c.y)
+    elif op == 'TSX': c.x = c.nz(c.s)
+    elif op == 'TXS': c.s = c.x
+    elif op == 'INX': c.x = c.nz(c.x + 1)
+    elif op == 'DEX': c.x = c.nz(c.x - 1)
+    elif op == 'INY': c.y = c.nz(c.y + 1)
+    elif op == 'DEY': c.y = c.nz(c.y - 1)
+    elif op == 'CLC': c.setf(C, False)
+    elif op == 'SEC': c.setf(C, True)
+    elif op == 'CLI': c.setf(I, False)
+    elif op == 'SEI': c.setf(I, True)
+    elif op == 'CLD': c.setf(D, False)
+    elif op == 'SED': c.setf(D, True)
+    elif op == 'CLV': c.setf(V, False)
+    elif op == 'NOP': pass
+    else: raise Exception(op)
+
+def vector(rng, opcode, cond):
+    op, mode, _ = CP[opcode]
+    while True:
+        c = Cpu(rng)
+        c.pc = rng.randrange(0x10000)
+        c.a, c.x, c.y, c.s = (rng.randrange(256) for _ in range(4))
+        c.p = (rng.randrange(256) | U) & ~B & 0xFF
+        try:
+            n = LEN[mode]
+            ops = [opcode] + [rng.randrange(256) for _ in range(n - 1)]
+            if op in BRANCH:
+                f, want = BRANCH[op]
+                c.setf(f, want if cond != 'not taken' else not want)
+            if mode in ('ABS_X', 'ABS_Y', 'IND_Y') and cond is not None:
+                index = c.x if mode == 'ABS_X' else c.y
+                if mode == 'IND_Y':
+                    z = ops[1]
+                    lo = rng.randrange(256)
+                    if (lo + index > 0xFF) != (cond == 'cross'):
+                        raise Retry()
+                    c.place(z, lo)
+                    c.place((z + 1) & 0xFF, rng.randrange(256))
+                elif (ops[1] + index > 0xFF) != (cond == 'cross'):
+                    raise Retry()
+            for i, b in enumerate(ops):
+                c.place(c.pc + i, b)
+            if op in BRANCH and cond in ('same page', 'cross'):
+                nxt = (c.pc + 2) & 0xFFFF
+                off = ops[1]
+                tgt = (nxt + (off - 256 if off & 0x80 else off)) & 0xFFFF
+                if (tgt >> 8 != nxt >> 8) != (cond == 'cross'):
+                    raise Retry()
+            initial = dict(pc=c.pc, s=c.s, a=c.a, x=c.x, y=c.y, p=c.p)
+            execute(c, opcode, op, mode)
+        except Retry:
+            continue
+        final = dict(pc=c.pc, s=c.s, a=c.a, x=c.x, y=c.y, p=c.p)
+        initial['ram'] = [[a, v] for a, v in c.init.items()]
+        final['ram'] = [[a, c.mem[a]] for a in c.init]
+        name = ' '.join(f'{b:02x}' for b in ops)
+        return dict(name=name, initial=initial, final=final, cycles=c.log)
+
+def conditions(opcode):
+    op, mode, _ = CP[opcode]
+    if op in BRANCH:
+        return ['not taken', 'same page', 'cross']
+    if mode in ('ABS_X', 'ABS_Y', 'IND_Y'):
+        return ['same page', 'cross']
+    return [None, None]
+
+def dump(v):
+    def st(s):
+        return '{ ' + ', '.join(f'"{k}": {json.dumps(s[k], separators=(", ", ": "))}' for k in ['pc','s','a','x','y','p','ram']) + ' }'
+    cyc = json.dumps(v['cycles'], separators=(', ', ': '))
+    return ('  {\n' f'    "name": "{v["name"]}",\n' f'    "initial": {st(v["initial"])},\n'
+            f'    "final": {st(v["final"])},\n' f'    "cycles": {cyc}\n' '  }')
+
+if __name__ == '__main__':
+    for opcode in range(256):
+        if CP[opcode][0] == 'JAM':
+            continue
+        rng = random.Random(opcode)
+        vs = [vector(rng, opcode, cond) for cond in conditions(opcode)]
+        path = os.path.join(ROOT, f'{opcode:02x}.json')
+        with open(path, 'w') as f:
+            f.write('[\n' + ',\n'.join(dump(v) for v in vs) + '\n]\n')
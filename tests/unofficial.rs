@@ -0,0 +1,296 @@
+mod common;
+
+#[cfg(test)]
+mod test_unofficial {
+    use crate::common::flat_ram;
+    use crate::common::step;
+    use remun::ExecutionErrorReason;
+    use remun::State;
+    use shared::flags;
+
+    /// The N, V, Z and C flags, in that order.
+    fn nvzc(state: &State) -> [bool; 4] {
+        [flags::N, flags::V, flags::Z, flags::C].map(|f| state.get_flag(f))
+    }
+
+    #[test]
+    fn test_slo() {
+        // ASL $10, then ORA
+        let mut state = step(0x0200, &[0x07, 0x10], |s| {
+            s.write(0x0010, 0xC1);
+            s.a = 0x10;
+        });
+        assert_eq!(state.read(0x0010, true), 0x82);
+        assert_eq!(state.a, 0x92);
+        assert_eq!(nvzc(&state), [true, false, false, true]);
+        assert_eq!(state.cycles, 5);
+    }
+
+    #[test]
+    fn test_rla() {
+        // ROL $10 with the carry going in, then AND
+        let mut state = step(0x0200, &[0x27, 0x10], |s| {
+            s.write(0x0010, 0x81);
+            s.a = 0x0F;
+            s.set_flag(flags::C, true);
+        });
+        assert_eq!(state.read(0x0010, true), 0x03);
+        assert_eq!(state.a, 0x03);
+        assert_eq!(nvzc(&state), [false, false, false, true]);
+    }
+
+    #[test]
+    fn test_sre() {
+        // LSR $10, then EOR
+        let mut state = step(0x0200, &[0x47, 0x10], |s| {
+            s.write(0x0010, 0x03);
+            s.a = 0x80;
+        });
+        assert_eq!(state.read(0x0010, true), 0x01);
+        assert_eq!(state.a, 0x81);
+        assert_eq!(nvzc(&state), [true, false, false, true]);
+    }
+
+    #[test]
+    fn test_rra() {
+        // ROR $10, the bit shifted out is the carry into ADC
+        let mut state = step(0x0200, &[0x67, 0x10], |s| {
+            s.write(0x0010, 0x03);
+            s.a = 0x7F;
+        });
+        assert_eq!(state.read(0x0010, true), 0x01);
+        assert_eq!(state.a, 0x81);
+        assert_eq!(nvzc(&state), [true, true, false, false]);
+    }
+
+    #[test]
+    fn test_sax() {
+        let mut state = step(0x0200, &[0x87, 0x10], |s| {
+            s.a = 0xF0;
+            s.x = 0x3C;
+            s.set_flag(flags::Z, true);
+        });
+        assert_eq!(state.read(0x0010, true), 0x30);
+        // flags are left alone
+        assert_eq!(nvzc(&state), [false, false, true, false]);
+    }
+
+    #[test]
+    fn test_lax() {
+        let state = step(0x0200, &[0xA7, 0x10], |s| s.write(0x0010, 0x80));
+        assert_eq!((state.a, state.x), (0x80, 0x80));
+        assert_eq!(nvzc(&state), [true, false, false, false]);
+    }
+
+    #[test]
+    fn test_dcp() {
+        // DEC $10 then CMP, which ignores the carry and leaves V alone
+        let mut state = step(0x0200, &[0xC7, 0x10], |s| {
+            s.write(0x0010, 0x05);
+            s.a = 0x04;
+            s.set_flag(flags::V, true);
+        });
+        assert_eq!(state.read(0x0010, true), 0x04);
+        assert_eq!(state.a, 0x04);
+        assert_eq!(nvzc(&state), [false, true, true, true]);
+        let state = step(0x0200, &[0xC7, 0x10], |s| {
+            s.write(0x0010, 0x05);
+            s.a = 0x03;
+            s.set_flag(flags::C, true);
+        });
+        assert_eq!(nvzc(&state), [true, false, false, false]);
+    }
+
+    #[test]
+    fn test_isc() {
+        // INC $10 then SBC, borrowing with the carry clear
+        let mut state = step(0x0200, &[0xE7, 0x10], |s| {
+            s.write(0x0010, 0x04);
+            s.a = 0x10;
+        });
+        assert_eq!(state.read(0x0010, true), 0x05);
+        assert_eq!(state.a, 0x0A);
+        assert_eq!(nvzc(&state), [false, false, false, true]);
+        let state = step(0x0200, &[0xE7, 0x10], |s| {
+            s.a = 0x80;
+            s.set_flag(flags::C, true);
+        });
+        assert_eq!(state.a, 0x7F);
+        assert_eq!(nvzc(&state), [false, true, false, true]);
+    }
+
+    #[test]
+    fn test_anc() {
+        let state = step(0x0200, &[0x0B, 0x80], |s| s.a = 0xFF);
+        assert_eq!(state.a, 0x80);
+        // N is copied to C
+        assert_eq!(nvzc(&state), [true, false, false, true]);
+    }
+
+    #[test]
+    fn test_alr() {
+        let state = step(0x0200, &[0x4B, 0x03], |s| s.a = 0xFF);
+        assert_eq!(state.a, 0x01);
+        assert_eq!(nvzc(&state), [false, false, false, true]);
+    }
+
+    #[test]
+    fn test_arr() {
+        // AND then ROR, C is bit 6 and V is bit 6 xor bit 5
+        let state = step(0x0200, &[0x6B, 0x80], |s| s.a = 0xFF);
+        assert_eq!(state.a, 0x40);
+        assert_eq!(nvzc(&state), [false, true, false, true]);
+        let state = step(0x0200, &[0x6B, 0xFF], |s| {
+            s.a = 0xFF;
+            s.set_flag(flags::C, true);
+        });
+        assert_eq!(state.a, 0xFF);
+        assert_eq!(nvzc(&state), [true, false, false, true]);
+    }
+
+    #[test]
+    fn test_sbx() {
+        // (A & X) - M into X, like CMP the carry going in does not matter
+        let state = step(0x0200, &[0xCB, 0x10], |s| {
+            s.a = 0xF0;
+            s.x = 0x3C;
+        });
+        assert_eq!((state.a, state.x), (0xF0, 0x20));
+        assert_eq!(nvzc(&state), [false, false, false, true]);
+        let state = step(0x0200, &[0xCB, 0x31], |s| {
+            s.a = 0xF0;
+            s.x = 0x3C;
+            s.set_flag(flags::C, true);
+        });
+        assert_eq!(state.x, 0xFF);
+        assert_eq!(nvzc(&state), [true, false, false, false]);
+    }
+
+    #[test]
+    fn test_usbc() {
+        // $EB is SBC #imm
+        let state = step(0x0200, &[0xEB, 0x01], |s| {
+            s.a = 0x10;
+            s.set_flag(flags::C, true);
+        });
+        assert_eq!(state.a, 0x0F);
+        assert_eq!(nvzc(&state), [false, false, false, true]);
+    }
+
+    #[test]
+    fn test_las() {
+        // LAS $10F0,Y crossing into $1100
+        let state = step(0x0200, &[0xBB, 0xF0, 0x10], |s| {
+            s.write(0x1100, 0xF3);
+            s.y = 0x10;
+            s.sp = 0x3F;
+        });
+        assert_eq!((state.a, state.x, state.sp), (0x33, 0x33, 0x33));
+        assert_eq!(nvzc(&state), [false, false, false, false]);
+        assert_eq!(state.cycles, 5);
+    }
+
+    #[test]
+    fn test_ane() {
+        // (A | magic) & X & M
+        let state = step(0x0200, &[0x8B, 0xFF], |s| s.x = 0xFF);
+        assert_eq!(state.a, 0xEE);
+        assert_eq!(nvzc(&state), [true, false, false, false]);
+        let state = step(0x0200, &[0x8B, 0xFF], |s| {
+            s.x = 0xFF;
+            s.magic = 0x00;
+        });
+        assert_eq!(state.a, 0x00);
+        assert_eq!(nvzc(&state), [false, false, true, false]);
+        let state = step(0x0200, &[0x8B, 0xFF], |s| {
+            s.a = 0x11;
+            s.x = 0x0F;
+        });
+        assert_eq!(state.a, 0x0F);
+    }
+
+    #[test]
+    fn test_lxa() {
+        // (A | magic) & M into A and X
+        let state = step(0x0200, &[0xAB, 0xF5], |_| {});
+        assert_eq!((state.a, state.x), (0xE4, 0xE4));
+        assert_eq!(nvzc(&state), [true, false, false, false]);
+        let state = step(0x0200, &[0xAB, 0xF5], |s| s.magic = 0xFF);
+        assert_eq!((state.a, state.x), (0xF5, 0xF5));
+    }
+
+    /// Runs a SHA/SHX/SHY/TAS store to $12F0 indexed by $0F and by $10,
+    /// returns both states. The stored value is anded with $12 + 1.
+    fn store_high(bytes: &[u8], setup: impl Fn(&mut State)) -> (State, State) {
+        let indexed = |index| {
+            step(0x0200, bytes, |s| {
+                s.write(0x0020, 0xF0);
+                s.write(0x0021, 0x12);
+                s.a = 0xFF;
+                s.x = index;
+                s.y = index;
+                setup(s);
+            })
+        };
+        (indexed(0x0F), indexed(0x10))
+    }
+
+    #[test]
+    fn test_sha() {
+        // SHA $12F0,Y and SHA ($20),Y
+        for bytes in [&[0x9F, 0xF0, 0x12][..], &[0x93, 0x20]] {
+            let (mut same_page, mut crossed) = store_high(bytes, |s| s.x = 0x05);
+            assert_eq!(same_page.read(0x12FF, true), 0x01);
+            // the value replaces the high byte of the address
+            assert_eq!(crossed.read(0x1300, true), 0x00);
+            assert_eq!(crossed.read(0x0100, true), 0x01);
+        }
+    }
+
+    #[test]
+    fn test_shx() {
+        let (mut same_page, mut crossed) = store_high(&[0x9E, 0xF0, 0x12], |s| s.x = 0x05);
+        assert_eq!(same_page.read(0x12FF, true), 0x01);
+        assert_eq!(crossed.read(0x1300, true), 0x00);
+        assert_eq!(crossed.read(0x0100, true), 0x01);
+        assert_eq!(crossed.cycles, 5);
+    }
+
+    #[test]
+    fn test_shy() {
+        let (mut same_page, mut crossed) = store_high(&[0x9C, 0xF0, 0x12], |s| s.y = 0x05);
+        assert_eq!(same_page.read(0x12FF, true), 0x01);
+        assert_eq!(crossed.read(0x1300, true), 0x00);
+        assert_eq!(crossed.read(0x0100, true), 0x01);
+    }
+
+    #[test]
+    fn test_tas() {
+        // SP = A & X, then stored like SHA
+        let (mut same_page, mut crossed) = store_high(&[0x9B, 0xF0, 0x12], |s| s.x = 0x05);
+        assert_eq!(same_page.sp, 0x05);
+        assert_eq!(same_page.read(0x12FF, true), 0x01);
+        assert_eq!(crossed.read(0x1300, true), 0x00);
+        assert_eq!(crossed.read(0x0100, true), 0x01);
+    }
+
+    #[test]
+    fn test_nops() {
+        // NOP #imm, NOP $1234 and NOP $10F0,X crossing a page
+        let state = step(0x0200, &[0x80, 0xFF], |_| {});
+        assert_eq!((state.pc, state.cycles), (0x0202, 2));
+        let state = step(0x0200, &[0x0C, 0x34, 0x12], |_| {});
+        assert_eq!((state.pc, state.cycles), (0x0203, 4));
+        let state = step(0x0200, &[0x1C, 0xF0, 0x10], |s| s.x = 0x10);
+        assert_eq!((state.pc, state.cycles), (0x0203, 5));
+        assert_eq!(nvzc(&state), [false; 4]);
+    }
+
+    #[test]
+    fn test_jam() {
+        let mut state = flat_ram(0x0200, &[0x02]);
+        let error = state.run_one_instruction().unwrap_err();
+        assert_eq!(error.reason, ExecutionErrorReason::Jam);
+        assert_eq!(state.pc, 0x0200);
+    }
+}
@@ -0,0 +1,38 @@
+#[cfg(test)]
+mod test_wav {
+    use remun::wav::{HEADER_SIZE, write_wav};
+
+    #[test]
+    fn test_header() {
+        let mut bytes = Vec::new();
+        write_wav(&mut bytes, 44100, &[0.0, 0.5, -2.0]).unwrap();
+        assert_eq!(bytes.len(), HEADER_SIZE + 6);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(bytes[4..8], 42u32.to_le_bytes());
+        assert_eq!(&bytes[8..16], b"WAVEfmt ");
+        // PCM, mono, 44100Hz, 16 bits
+        assert_eq!(bytes[20..24], [1, 0, 1, 0]);
+        assert_eq!(bytes[24..28], 44100u32.to_le_bytes());
+        assert_eq!(bytes[34..36], 16u16.to_le_bytes());
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(bytes[40..44], 6u32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_sample_rate_out_of_range() {
+        let mut bytes = Vec::new();
+        assert!(write_wav(&mut bytes, u32::MAX, &[0.0]).is_err());
+        assert!(bytes.is_empty());
+    }
+
+    #[test]
+    fn test_samples_are_scaled_and_clamped() {
+        let mut bytes = Vec::new();
+        write_wav(&mut bytes, 8000, &[0.0, 0.5, -2.0]).unwrap();
+        let samples: Vec<i16> = bytes[HEADER_SIZE..]
+            .chunks(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        assert_eq!(samples, [0, 16383, -32767]);
+    }
+}